@@ -0,0 +1,83 @@
+/*Auctions --> escrows whose price isn't fixed when the trade is set up*/
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+use crate::error::EscrowError;
+
+/// How an auction escrow sells its offered tokens instead of at a fixed price
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Auction {
+    Dutch(DutchAuction),
+    English(EnglishAuction),
+}
+
+//price schedule of a Dutch auction: the price for everything still open starts at `start_amount` and falls to the
+//escrow's expected amount (the floor) between `start_time` and `end_time`, then stays at the floor until the escrow times out
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DutchAuction {
+    pub start_amount: u64,
+    //unix timestamps in seconds
+    pub start_time: u64,
+    pub end_time: u64,
+    //the price only drops every `step` seconds, 0 lets it fall continuously
+    pub step: u64,
+}
+
+impl DutchAuction {
+    /// Whether the price can fall from `start_amount` to `floor_amount` in the auction's time span
+    pub fn is_valid(&self, floor_amount: u64) -> bool {
+        self.start_amount >= floor_amount && self.start_time < self.end_time
+    }
+
+    /// Price at unix timestamp `now` of everything still open when the floor is `floor_amount`
+    pub fn current_amount(&self, floor_amount: u64, now: u64) -> Result<u64, ProgramError> {
+        if !self.is_valid(floor_amount) {
+            return Err(EscrowError::InvalidInstruction.into());
+        }
+        if now >= self.end_time {
+            return Ok(floor_amount);
+        }
+
+        let mut elapsed = now.saturating_sub(self.start_time);
+        if self.step > 0 {
+            elapsed -= elapsed % self.step;
+        }
+        //the discount is rounded down, so the price always favours the initializer
+        let discount = ((self.start_amount - floor_amount) as u128)
+            .checked_mul(elapsed as u128)
+            .map(|product| product / (self.end_time - self.start_time) as u128)
+            .ok_or(EscrowError::AmountOverflow)?;
+        Ok(self.start_amount - discount as u64)
+    }
+}
+
+//rules of an English auction: bids start at the escrow's expected amount (the reserve price) and each one has to beat the
+//highest bid by at least `min_increment`. The auction ends at the escrow's time_out, after that anyone can settle it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EnglishAuction {
+    pub min_increment: u64,
+}
+
+impl EnglishAuction {
+    /// Smallest bid accepted after `highest_bid`, when bids start at `reserve_amount`
+    pub fn min_bid(
+        &self,
+        reserve_amount: u64,
+        highest_bid: Option<&Bid>,
+    ) -> Result<u64, ProgramError> {
+        match highest_bid {
+            //a new bid always has to be higher, even without an increment
+            Some(bid) => bid
+                .amount
+                .checked_add(self.min_increment.max(1))
+                .ok_or_else(|| EscrowError::AmountOverflow.into()),
+            None => Ok(reserve_amount),
+        }
+    }
+}
+
+//the bid currently winning an English auction, its tokens are held in the escrow's bid token account
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Bid {
+    pub bidder: Pubkey,
+    pub amount: u64,
+}
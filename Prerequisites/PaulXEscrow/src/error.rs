@@ -28,6 +28,58 @@ pub enum EscrowError {
     /// Escrow Time Unlock
     #[error("Escrow Time Lock")]
     EscrowTimeUnlock,
+
+    /// Offered Mint Mismatch
+    #[error("Offered Mint Mismatch")]
+    OfferedMintMismatch,
+
+    /// Requested Mint Mismatch
+    #[error("Requested Mint Mismatch")]
+    RequestedMintMismatch,
+
+    /// Invalid Fill Amount
+    #[error("Invalid Fill Amount")]
+    InvalidFillAmount,
+
+    /// Invalid Fee
+    #[error("Invalid Fee")]
+    InvalidFee,
+
+    /// Invalid Fee Account
+    #[error("Invalid Fee Account")]
+    InvalidFeeAccount,
+
+    /// Unauthorized
+    #[error("Unauthorized")]
+    Unauthorized,
+
+    /// Feature Disabled
+    #[error("Feature Disabled")]
+    FeatureDisabled,
+
+    /// Program Paused
+    #[error("Program Paused")]
+    ProgramPaused,
+
+    /// Taker Mismatch
+    #[error("Taker Mismatch")]
+    TakerMismatch,
+
+    /// Escrow Not Expired
+    #[error("Escrow Not Expired")]
+    EscrowNotExpired,
+
+    /// Price Limit Exceeded
+    #[error("Price Limit Exceeded")]
+    PriceLimitExceeded,
+
+    /// Invalid Escrow Mode
+    #[error("Invalid Escrow Mode")]
+    InvalidEscrowMode,
+
+    /// Bid Too Low
+    #[error("Bid Too Low")]
+    BidTooLow,
 }
 
 //implementing a generic trait - "From" trait. The reason we do this conversion is that the entrypoint returns a Result of either nothing or a ProgramError.
@@ -0,0 +1,310 @@
+/*Program events --> binary records logged with sol_log_data so indexers don't have to scrape msg! lines
+
+Every event is logged as a single "Program data: <base64>" line, the decoded bytes are
+[version][tag][fields...] with pubkeys as 32 raw bytes and amounts/timestamps as little endian u64.
+*/
+use solana_program::{log::sol_log_data, program_error::ProgramError, pubkey::Pubkey};
+use std::convert::TryInto;
+
+/// Version of the event encoding, bumped whenever a field is added to any event
+pub const EVENT_VERSION: u8 = 2;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EscrowEvent {
+    /// An escrow was initialized and its temp token account handed to the escrow's PDA
+    Created {
+        escrow: Pubkey,
+        initializer: Pubkey,
+        offered_mint: Pubkey,
+        requested_mint: Pubkey,
+        /// Amount of the offered token held in the temp token account
+        offered_amount: u64,
+        /// Amount of the requested token the initializer expects for all of it
+        expected_amount: u64,
+        unlock_time: u64,
+        time_out: u64,
+    },
+    /// A taker filled the escrow, fully or partially
+    Exchanged {
+        escrow: Pubkey,
+        initializer: Pubkey,
+        taker: Pubkey,
+        offered_mint: Pubkey,
+        requested_mint: Pubkey,
+        /// Amount of the offered token taken out of the escrow
+        offered_amount: u64,
+        /// Amount of the requested token paid by the taker (not counting any transfer fee of a Token-2022 mint)
+        requested_amount: u64,
+        /// Amount of the offered token still open, 0 once the escrow is closed
+        remaining_amount: u64,
+        /// Protocol fee kept from `offered_amount` (0 in version 1 events)
+        offered_fee: u64,
+        /// Protocol fee kept from `requested_amount` (0 in version 1 events)
+        requested_fee: u64,
+    },
+    /// The initializer cancelled the escrow and got the offered tokens back
+    Cancelled {
+        escrow: Pubkey,
+        initializer: Pubkey,
+        offered_mint: Pubkey,
+        refunded_amount: u64,
+    },
+    /// The escrow timed out and the offered tokens were refunded to the initializer
+    Expired {
+        escrow: Pubkey,
+        initializer: Pubkey,
+        offered_mint: Pubkey,
+        /// 0 for a delegated escrow, its tokens never left the initializer's account
+        refunded_amount: u64,
+    },
+    /// The initializer changed the price or time window of an open escrow
+    Updated {
+        escrow: Pubkey,
+        initializer: Pubkey,
+        /// Amount of the requested token now expected for everything still open
+        expected_amount: u64,
+        unlock_time: u64,
+        time_out: u64,
+    },
+    /// A bid became the highest bid of an English auction, the previous one was refunded
+    BidPlaced {
+        escrow: Pubkey,
+        bidder: Pubkey,
+        amount: u64,
+    },
+}
+
+impl EscrowEvent {
+    /// Logs the event as a "Program data:" entry of the transaction
+    pub fn emit(&self) {
+        sol_log_data(&[&self.pack()]);
+    }
+
+    /// Packs the event into the bytes that get logged
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = vec![EVENT_VERSION];
+        match self {
+            Self::Created {
+                escrow,
+                initializer,
+                offered_mint,
+                requested_mint,
+                offered_amount,
+                expected_amount,
+                unlock_time,
+                time_out,
+            } => {
+                buf.push(0);
+                buf.extend_from_slice(escrow.as_ref());
+                buf.extend_from_slice(initializer.as_ref());
+                buf.extend_from_slice(offered_mint.as_ref());
+                buf.extend_from_slice(requested_mint.as_ref());
+                buf.extend_from_slice(&offered_amount.to_le_bytes());
+                buf.extend_from_slice(&expected_amount.to_le_bytes());
+                buf.extend_from_slice(&unlock_time.to_le_bytes());
+                buf.extend_from_slice(&time_out.to_le_bytes());
+            }
+            Self::Exchanged {
+                escrow,
+                initializer,
+                taker,
+                offered_mint,
+                requested_mint,
+                offered_amount,
+                requested_amount,
+                remaining_amount,
+                offered_fee,
+                requested_fee,
+            } => {
+                buf.push(1);
+                buf.extend_from_slice(escrow.as_ref());
+                buf.extend_from_slice(initializer.as_ref());
+                buf.extend_from_slice(taker.as_ref());
+                buf.extend_from_slice(offered_mint.as_ref());
+                buf.extend_from_slice(requested_mint.as_ref());
+                buf.extend_from_slice(&offered_amount.to_le_bytes());
+                buf.extend_from_slice(&requested_amount.to_le_bytes());
+                buf.extend_from_slice(&remaining_amount.to_le_bytes());
+                buf.extend_from_slice(&offered_fee.to_le_bytes());
+                buf.extend_from_slice(&requested_fee.to_le_bytes());
+            }
+            Self::Cancelled {
+                escrow,
+                initializer,
+                offered_mint,
+                refunded_amount,
+            } => {
+                buf.push(2);
+                buf.extend_from_slice(escrow.as_ref());
+                buf.extend_from_slice(initializer.as_ref());
+                buf.extend_from_slice(offered_mint.as_ref());
+                buf.extend_from_slice(&refunded_amount.to_le_bytes());
+            }
+            Self::Expired {
+                escrow,
+                initializer,
+                offered_mint,
+                refunded_amount,
+            } => {
+                buf.push(3);
+                buf.extend_from_slice(escrow.as_ref());
+                buf.extend_from_slice(initializer.as_ref());
+                buf.extend_from_slice(offered_mint.as_ref());
+                buf.extend_from_slice(&refunded_amount.to_le_bytes());
+            }
+            Self::Updated {
+                escrow,
+                initializer,
+                expected_amount,
+                unlock_time,
+                time_out,
+            } => {
+                buf.push(4);
+                buf.extend_from_slice(escrow.as_ref());
+                buf.extend_from_slice(initializer.as_ref());
+                buf.extend_from_slice(&expected_amount.to_le_bytes());
+                buf.extend_from_slice(&unlock_time.to_le_bytes());
+                buf.extend_from_slice(&time_out.to_le_bytes());
+            }
+            Self::BidPlaced {
+                escrow,
+                bidder,
+                amount,
+            } => {
+                buf.push(5);
+                buf.extend_from_slice(escrow.as_ref());
+                buf.extend_from_slice(bidder.as_ref());
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+        }
+        buf
+    }
+
+    /// Unpacks the (base64 decoded) bytes of a "Program data:" log entry into an event.
+    /// Fields are only ever appended, so events of a newer version decode too and their extra fields are ignored.
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        let (version, rest) = input.split_first().ok_or(ProgramError::InvalidArgument)?;
+        if *version == 0 {
+            return Err(ProgramError::InvalidArgument);
+        }
+        let (tag, rest) = rest.split_first().ok_or(ProgramError::InvalidArgument)?;
+
+        Ok(match tag {
+            0 => {
+                let (escrow, rest) = Self::unpack_pubkey(rest)?;
+                let (initializer, rest) = Self::unpack_pubkey(rest)?;
+                let (offered_mint, rest) = Self::unpack_pubkey(rest)?;
+                let (requested_mint, rest) = Self::unpack_pubkey(rest)?;
+                let (offered_amount, rest) = Self::unpack_u64(rest)?;
+                let (expected_amount, rest) = Self::unpack_u64(rest)?;
+                let (unlock_time, rest) = Self::unpack_u64(rest)?;
+                let (time_out, _rest) = Self::unpack_u64(rest)?;
+                Self::Created {
+                    escrow,
+                    initializer,
+                    offered_mint,
+                    requested_mint,
+                    offered_amount,
+                    expected_amount,
+                    unlock_time,
+                    time_out,
+                }
+            }
+            1 => {
+                let (escrow, rest) = Self::unpack_pubkey(rest)?;
+                let (initializer, rest) = Self::unpack_pubkey(rest)?;
+                let (taker, rest) = Self::unpack_pubkey(rest)?;
+                let (offered_mint, rest) = Self::unpack_pubkey(rest)?;
+                let (requested_mint, rest) = Self::unpack_pubkey(rest)?;
+                let (offered_amount, rest) = Self::unpack_u64(rest)?;
+                let (requested_amount, rest) = Self::unpack_u64(rest)?;
+                let (remaining_amount, rest) = Self::unpack_u64(rest)?;
+                //version 1 had no fees
+                let (offered_fee, rest) = match version {
+                    1 => (0, rest),
+                    _ => Self::unpack_u64(rest)?,
+                };
+                let (requested_fee, _rest) = match version {
+                    1 => (0, rest),
+                    _ => Self::unpack_u64(rest)?,
+                };
+                Self::Exchanged {
+                    escrow,
+                    initializer,
+                    taker,
+                    offered_mint,
+                    requested_mint,
+                    offered_amount,
+                    requested_amount,
+                    remaining_amount,
+                    offered_fee,
+                    requested_fee,
+                }
+            }
+            2 | 3 => {
+                let (escrow, rest) = Self::unpack_pubkey(rest)?;
+                let (initializer, rest) = Self::unpack_pubkey(rest)?;
+                let (offered_mint, rest) = Self::unpack_pubkey(rest)?;
+                let (refunded_amount, _rest) = Self::unpack_u64(rest)?;
+                if *tag == 2 {
+                    Self::Cancelled {
+                        escrow,
+                        initializer,
+                        offered_mint,
+                        refunded_amount,
+                    }
+                } else {
+                    Self::Expired {
+                        escrow,
+                        initializer,
+                        offered_mint,
+                        refunded_amount,
+                    }
+                }
+            }
+            4 => {
+                let (escrow, rest) = Self::unpack_pubkey(rest)?;
+                let (initializer, rest) = Self::unpack_pubkey(rest)?;
+                let (expected_amount, rest) = Self::unpack_u64(rest)?;
+                let (unlock_time, rest) = Self::unpack_u64(rest)?;
+                let (time_out, _rest) = Self::unpack_u64(rest)?;
+                Self::Updated {
+                    escrow,
+                    initializer,
+                    expected_amount,
+                    unlock_time,
+                    time_out,
+                }
+            }
+            5 => {
+                let (escrow, rest) = Self::unpack_pubkey(rest)?;
+                let (bidder, rest) = Self::unpack_pubkey(rest)?;
+                let (amount, _rest) = Self::unpack_u64(rest)?;
+                Self::BidPlaced {
+                    escrow,
+                    bidder,
+                    amount,
+                }
+            }
+            _ => return Err(ProgramError::InvalidArgument),
+        })
+    }
+
+    fn unpack_pubkey(input: &[u8]) -> Result<(Pubkey, &[u8]), ProgramError> {
+        let pubkey = input
+            .get(..32)
+            .and_then(|slice| slice.try_into().ok())
+            .map(Pubkey::new_from_array)
+            .ok_or(ProgramError::InvalidArgument)?;
+        Ok((pubkey, &input[32..]))
+    }
+
+    fn unpack_u64(input: &[u8]) -> Result<(u64, &[u8]), ProgramError> {
+        let value = input
+            .get(..8)
+            .and_then(|slice| slice.try_into().ok())
+            .map(u64::from_le_bytes)
+            .ok_or(ProgramError::InvalidArgument)?;
+        Ok((value, &input[8..]))
+    }
+}
@@ -0,0 +1,46 @@
+/*Protocol fee --> how much of each settled trade goes to the protocol*/
+use solana_program::program_error::ProgramError;
+
+use crate::error::EscrowError;
+
+/// Basis points in 100%
+pub const MAX_FEE_BPS: u16 = 10_000;
+
+//fee rates in basis points for each leg of the trade, 0 turns the fee off for that leg.
+//The rates the protocol actually charges live in state::Config
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Fee {
+    //skimmed from the offered tokens before they reach the taker
+    pub offered_bps: u16,
+    //skimmed from the taker's payment before it reaches the initializer
+    pub requested_bps: u16,
+}
+
+impl Fee {
+    /// Whether both rates are within 0..=MAX_FEE_BPS
+    pub fn is_valid(&self) -> bool {
+        self.offered_bps <= MAX_FEE_BPS && self.requested_bps <= MAX_FEE_BPS
+    }
+
+    /// Fee taken from `amount` offered tokens sent to the taker
+    pub fn offered_fee(&self, amount: u64) -> Result<u64, ProgramError> {
+        Self::fee(self.offered_bps, amount)
+    }
+
+    /// Fee taken from `amount` requested tokens sent to the initializer
+    pub fn requested_fee(&self, amount: u64) -> Result<u64, ProgramError> {
+        Self::fee(self.requested_bps, amount)
+    }
+
+    //rounded down, so a fee never takes more than its rate
+    fn fee(bps: u16, amount: u64) -> Result<u64, ProgramError> {
+        if bps > MAX_FEE_BPS {
+            return Err(EscrowError::InvalidFee.into());
+        }
+        let fee = (amount as u128)
+            .checked_mul(bps as u128)
+            .map(|product| product / MAX_FEE_BPS as u128)
+            .ok_or(EscrowError::AmountOverflow)?;
+        u64::try_from(fee).map_err(|_| EscrowError::AmountOverflow.into())
+    }
+}
@@ -1,43 +1,296 @@
-/*Program API --> (de)serializes instruction data*/
-use solana_program::program_error::ProgramError;
+/*Program API --> (de)serializes instruction data and builds instructions for clients*/
+use solana_program::{
+    bpf_loader_upgradeable,
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program, sysvar,
+};
 use std::convert::TryInto;
 
-use crate::error::EscrowError::InvalidInstruction;
+use crate::{
+    auction::{Auction, DutchAuction, EnglishAuction},
+    error::EscrowError::InvalidInstruction,
+    fee::Fee,
+    state::{Config, Escrow},
+};
 
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum EscrowInstruction {
-    /// Starts the trade by creating and populating an escrow account and transferring ownership of the given temp token account to the PDA
+    /// Starts the trade by creating and populating an escrow account and transferring ownership of the given temp token account to the escrow's PDA
     ///
     ///
     /// Accounts expected: Alice --> initializer , Bob -> Taker (in this example)
     ///
     /// 0. `[signer]` The account of the person initializing the escrow
-    /// 1. `[writable]` Temporary token account that should be created prior to this instruction and owned by the initializer (writable since ownership is changing)
+    /// 1. `[writable]` Temporary token account that should be created prior to this instruction and owned by the initializer (writable since ownership is changing), it may not have a close authority
     /// 2. `[]` The initializer's token account for the token they will receive should the trade go through (eventually written to, but not in this transaction)
     /// 3. `[writable]` The escrow account, it will hold all necessary info about the trade.
     /// 4. `[]` The rent sysvar (explained in processor.rs code) --> Solana has sysvars that are parameters of the Solana cluster you are on. These sysvars can be accessed through accounts and store parameters such as what the current fee or rent is.
-    /// 5. `[]` The token program (token program itself!)
+    /// 5. `[]` The token program (token program itself!) owning the temp token account, either the original one or Token-2022
+    /// 6. `[]` The config account (seeds: `b"config"`), no escrows can be opened while it's paused
+    /// 7. `[]` The mint of the offered token (its decimals are recorded and checked on every transfer)
+    /// 8. `[]` The mint of the requested token (its decimals are recorded and checked on every transfer)
+    /// 9. `[writable]` English auctions only: an empty token account of the requested token owned by the initializer
+    ///    without a close authority, ownership moves to the escrow's PDA and it holds the highest bid from then on
+    /// 10. `[]` English auctions only: the token program of the requested token
+    ///
+    /// When a `nonce` is given the initializer also pays for the escrow account, so it has to be writable and account 3 is the
+    /// not yet existing account at `Escrow::find_address`.
+    ///
+    /// When a `deposit` is given the initializer pays for the vault token account that replaces the temp one, so it has to
+    /// be writable and account 1 is the not yet existing account at `Escrow::find_vault_address`. The accounts above are then followed by
+    /// - `[writable]` The initializer's token account of the offered token the deposit is paid from
+    ///
+    /// When a `delegated_amount` is given account 1 is the initializer's own token account of the offered token, which keeps
+    /// the tokens until the trade is taken. Its approval for the initializer's delegate PDA grows by the delegated amount and
+    /// the accounts above are followed by
+    /// - `[]` The initializer's delegate PDA (seeds: `b"delegate"` + initializer key)
+    ///
+    /// and with a `nonce` or a `deposit` the last account is
+    /// - `[]` The system program
+    ///
+    /// Either leg may be native SOL (not in an English auction), the system program (`native::MINT`) is then passed as its
+    /// mint. Requested SOL is paid into the initializer's wallet, which is passed as account 2. Offered SOL needs a
+    /// `deposit`: account 1 is the vault at `Escrow::find_vault_address` holding the lamports, account 5 is the system
+    /// program and the deposit is paid from the initializer's wallet, passed as the sending account.
     InitEscrow {
         /// The amount party A expects to receive of token Y. Provided not through account, but instruction data!
         amount: u64,
+        /// Unix timestamp before which the trade cannot be taken
+        unlock_time: u64,
+        /// Unix timestamp after which the trade cannot be taken anymore
+        time_out: u64,
+        /// The only account allowed to take the trade, anyone may take it if None. Encoded as a 6 byte followed by the key if set
+        taker: Option<Pubkey>,
+        /// Sells by auction instead of at a fixed price if set (encoded by leaving it out otherwise). For a Dutch auction
+        /// (a 1 byte followed by its four u64 fields) `amount` is the floor the price falls to, for an English auction
+        /// (a 2 byte followed by the min increment) it is the reserve price and no `taker` may be set
+        auction: Option<Auction>,
+        /// Has the program create the escrow account at the address derived from the initializer and this nonce
+        /// (see `Escrow::find_address`) instead of the client creating it. Encoded as a 3 byte followed by the u64 if set
+        nonce: Option<u64>,
+        /// Has the program create the vault token account holding the offered tokens and move this many of them into it from
+        /// the initializer's token account, instead of the client setting up a temp token account. The initializer also pays
+        /// any Token-2022 transfer fee, so the full amount is offered. Encoded as a 4 byte followed by the u64 if set
+        deposit: Option<u64>,
+        /// Offers this many tokens straight out of the initializer's token account, which approves the initializer's delegate
+        /// PDA to pull them when the trade is taken. Several escrows can share one account this way, nothing is locked up,
+        /// so a taker's exchange fails if the tokens are gone by then. Can't be combined with a `deposit` or an English
+        /// auction. Encoded as a 5 byte followed by the u64 if set
+        delegated_amount: Option<u64>,
     },
-    /// Accepts a trade
+    /// Accepts a trade (fully or partially)
     ///
     ///
     /// Accounts expected: Bob --> Taker here once again in this example
     ///
-    /// 0. `[signer]` The account of the person taking the trade
+    /// 0. `[signer]` The account of the person taking the trade (writable when it pays for its receiving token account)
     /// 1. `[writable]` The taker's token account for the token they send
-    /// 2. `[writable]` The taker's token account for the token they will receive should the trade go through
-    /// 3. `[writable]` The PDA's temp token account to get tokens from and eventually close
+    /// 2. `[writable]` The taker's token account for the token they will receive should the trade go through (their
+    ///    associated token account of the offered token when `create_receive_account` is set)
+    /// 3. `[writable]` The PDA's temp token account to get tokens from and eventually close (the initializer's token account
+    ///    for a delegated escrow, it's left open)
     /// 4. `[writable]` The initializer's main account to send their rent fees to
     /// 5. `[writable]` The initializer's token account that will receive tokens
     /// 6. `[writable]` The escrow account holding the escrow info
-    /// 7. `[]` The token program
-    /// 8. `[]` The PDA account
+    /// 7. `[]` The token program of the offered token
+    /// 8. `[]` The PDA account of this escrow (seeds: `b"escrow"` + escrow account key), the initializer's delegate PDA
+    ///    for a delegated escrow
+    /// 9. `[writable]` The protocol's fee token account for the offered token (owned by the config's fee owner), any
+    ///    account if no fee is taken on that leg
+    /// 10. `[writable]` The protocol's fee token account for the requested token (owned by the config's fee owner), any
+    ///     account if no fee is taken on that leg
+    /// 11. `[]` The config account (seeds: `b"config"`), no escrows can be taken while it's paused
+    /// 12. `[]` The token program of the requested token (may differ from the offered token's)
+    /// 13. `[writable]` The mint of the offered token (writable so withheld Token-2022 transfer fees can be harvested into it)
+    /// 14. `[]` The mint of the requested token
+    ///
+    /// When `create_receive_account` is set these are followed by
+    /// 15. `[]` The system program
+    /// 16. `[]` The associated token account program
+    ///
+    /// A native SOL leg takes the system program as its token program and mint. Offered SOL is paid out of the vault (account 3)
+    /// into any account 2, can't be combined with `create_receive_account`, and its fee goes to the fee owner's wallet
+    /// (account 9). Requested SOL is paid from the taker's wallet (account 1) into the initializer's (account 5), and its
+    /// fee into the fee owner's wallet (account 10).
     Exchange {
-        /// the amount the taker expects to be paid in the other token, as a u64 because that's the max possible supply of a token
+        /// the amount of the offered token the taker wants to take, as a u64 because that's the max possible supply of a token.
+        /// Taking less than everything in the temp token account fills the trade partially, the taker then pays the
+        /// pro-rata share of the expected amount (of the current price for a Dutch auction) and the rest of the trade stays open.
+        /// English auctions can't be exchanged, they are sold with `PlaceBid` and `SettleAuction`
+        amount: u64,
+        /// The most of the requested token the taker agrees to pay for `amount` (fees included, Token-2022 transfer fee
+        /// excluded), protects them from the initializer raising the price first. No limit if None (encoded by leaving it out)
+        max_requested_amount: Option<u64>,
+        /// Creates the taker's associated token account of the offered token first if it doesn't exist yet, so a first
+        /// time buyer can trade in one transaction. Encoded as a trailing 1 byte if set
+        create_receive_account: bool,
+    },
+    /// Cancels an untaken trade and refunds the initializer (English auctions can't be cancelled, they are settled)
+    ///
+    ///
+    /// Accounts expected: Alice --> initializer changing her mind before anyone takes the trade
+    ///
+    /// 0. `[signer, writable]` The account of the person who initialized the escrow (also receives the rent fees back)
+    /// 1. `[writable]` The PDA's temp token account to get tokens from and eventually close (the initializer's token account
+    ///    for a delegated escrow, its approval shrinks by what the escrow still offered)
+    /// 2. `[writable]` The initializer's token account that gets the offered tokens back
+    /// 3. `[writable]` The escrow account holding the escrow info
+    /// 4. `[]` The token program
+    /// 5. `[]` The PDA account of this escrow (seeds: `b"escrow"` + escrow account key), the initializer's delegate PDA
+    ///    for a delegated escrow
+    /// 6. `[writable]` The mint of the offered token (writable so withheld Token-2022 transfer fees can be harvested into it)
+    ///
+    /// For offered native SOL the system program is passed as the token program and mint, and the vault's lamports go back
+    /// to the initializer's wallet (account 2 is then unused).
+    Cancel,
+    /// Rewrites an escrow account stored in an older layout version to the current `Escrow::VERSION`
+    ///
+    ///
+    /// Accounts expected: anyone can migrate an escrow, the result only depends on what's already stored in it
+    ///
+    /// 0. `[signer, writable]` The account paying for the rent of the bigger escrow account
+    /// 1. `[writable]` The escrow account holding the escrow info
+    /// 2. `[writable]` The escrow's temp token account (its authority moves from the old program-wide PDA to the escrow's PDA)
+    /// 3. `[]` The initializer's token account that will receive tokens
+    /// 4. `[]` The token program
+    /// 5. `[]` The old program-wide PDA account (seeds: `b"escrow"`)
+    /// 6. `[]` The system program
+    /// 7. `[]` The mint of the offered token
+    /// 8. `[]` The mint of the requested token
+    MigrateEscrow,
+    /// Creates the program-wide config account, only the program's upgrade authority may do this
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The program's upgrade authority, pays for the config account
+    /// 1. `[writable]` The config account (seeds: `b"config"`)
+    /// 2. `[]` The program's ProgramData account (holds the upgrade authority)
+    /// 3. `[]` The system program
+    InitializeConfig {
+        /// The key allowed to update the config from now on
+        admin: Pubkey,
+        /// Wallet owning the token accounts the protocol fee is paid into
+        fee_owner: Pubkey,
+        /// Protocol fee rates in basis points
+        fee: Fee,
+        /// Bit set of the `Config::FEATURE_*` toggles to switch on
+        features: u64,
+        /// Lamports paid to whoever expires a timed out escrow, out of the rent it frees up
+        expire_bounty: u64,
+    },
+    /// Replaces the settings in the config account
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The config's current admin
+    /// 1. `[writable]` The config account (seeds: `b"config"`)
+    UpdateConfig {
+        /// The key allowed to update the config from now on (pass the current admin to keep it)
+        admin: Pubkey,
+        /// Wallet owning the token accounts the protocol fee is paid into
+        fee_owner: Pubkey,
+        /// Protocol fee rates in basis points
+        fee: Fee,
+        /// Bit set of the `Config::FEATURE_*` toggles to switch on
+        features: u64,
+        /// Lamports paid to whoever expires a timed out escrow, out of the rent it frees up
+        expire_bounty: u64,
+    },
+    /// Pauses or unpauses the program. While paused `InitEscrow`, `Exchange` and `PlaceBid` fail, `Cancel`, `Expire`
+    /// and `SettleAuction` still hand out what's in the escrows
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The config's admin
+    /// 1. `[writable]` The config account (seeds: `b"config"`)
+    SetPaused {
+        /// Whether new trades are refused
+        paused: bool,
+    },
+    /// Refunds a timed out escrow to its initializer and closes it, anyone may do this once `time_out` has passed
+    /// (English auctions are settled instead)
+    ///
+    ///
+    /// Accounts expected: a keeper bot cleaning up after Alice forgot about her offer
+    ///
+    /// 0. `[signer, writable]` The account expiring the escrow, receives the config's expire bounty
+    /// 1. `[writable]` The PDA's temp token account to get tokens from and eventually close (the initializer's token account
+    ///    for a delegated escrow, it's left as it is since only the initializer can change its approval)
+    /// 2. `[writable]` A token account of the offered token owned by the initializer, gets the offered tokens back
+    /// 3. `[writable]` The initializer's main account to send the remaining rent fees to
+    /// 4. `[writable]` The escrow account holding the escrow info
+    /// 5. `[]` The token program of the offered token
+    /// 6. `[]` The PDA account of this escrow (seeds: `b"escrow"` + escrow account key), the initializer's delegate PDA
+    ///    for a delegated escrow
+    /// 7. `[writable]` The mint of the offered token (writable so withheld Token-2022 transfer fees can be harvested into it)
+    /// 8. `[]` The config account (seeds: `b"config"`)
+    ///
+    /// For offered native SOL the system program is passed as the token program and mint, and the vault's lamports go to
+    /// the initializer's main account (account 2 is then unused).
+    Expire,
+    /// Changes the price of an open escrow (and optionally its time window) without cancelling and recreating it.
+    /// For a Dutch auction this moves the floor, which can't be raised above the auction's start amount
+    ///
+    ///
+    /// Accounts expected: Alice --> initializer repricing her offer
+    ///
+    /// 0. `[signer]` The account of the person who initialized the escrow
+    /// 1. `[writable]` The escrow account holding the escrow info
+    UpdateExpectedAmount {
+        /// The amount of the requested token expected for everything still left in the temp token account
         amount: u64,
+        /// New `(unlock_time, time_out)` of the trade, the old ones are kept if None (encoded by leaving them out)
+        deadlines: Option<(u64, u64)>,
     },
+    /// Bids on an English auction, the previous highest bid is refunded
+    ///
+    ///
+    /// Accounts expected: Bob --> bidder outbidding Carol
+    ///
+    /// 0. `[signer]` The account of the person bidding
+    /// 1. `[writable]` The bidder's token account of the requested token the bid is paid from
+    /// 2. `[writable]` The escrow account holding the escrow info
+    /// 3. `[writable]` The escrow's bid token account
+    /// 4. `[writable]` A token account of the requested token owned by the previous highest bidder, gets their bid back
+    ///    (any account if there is no bid yet)
+    /// 5. `[]` The token program of the requested token
+    /// 6. `[]` The mint of the requested token
+    /// 7. `[]` The PDA account of this escrow (seeds: `b"escrow"` + escrow account key)
+    /// 8. `[]` The config account (seeds: `b"config"`), no bids can be placed while it's paused
+    PlaceBid {
+        /// The amount of the requested token bid for everything in the temp token account. The bidder also pays any
+        /// Token-2022 transfer fee, so the full amount ends up in the bid token account
+        amount: u64,
+    },
+    /// Ends an English auction after its `time_out`: the highest bidder gets the offered tokens and the initializer the bid,
+    /// or the initializer gets the offered tokens back if nobody bid. Anyone may do this
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account settling the auction
+    /// 1. `[writable]` The PDA's temp token account to get tokens from and eventually close
+    /// 2. `[writable]` The escrow's bid token account to get the bid from and eventually close
+    /// 3. `[writable]` A token account of the offered token owned by the highest bidder (by the initializer if nobody bid)
+    /// 4. `[writable]` A token account of the requested token owned by the initializer, gets the bid
+    /// 5. `[writable]` The initializer's main account to send the rent fees to
+    /// 6. `[writable]` The escrow account holding the escrow info
+    /// 7. `[]` The token program of the offered token
+    /// 8. `[]` The token program of the requested token
+    /// 9. `[]` The PDA account of this escrow (seeds: `b"escrow"` + escrow account key)
+    /// 10. `[writable]` The mint of the offered token (writable so withheld Token-2022 transfer fees can be harvested into it)
+    /// 11. `[writable]` The mint of the requested token (same)
+    /// 12. `[writable]` The protocol's fee token account for the offered token (owned by the config's fee owner), any
+    ///     account if no fee is taken on that leg
+    /// 13. `[writable]` The protocol's fee token account for the requested token (owned by the config's fee owner), any
+    ///     account if no fee is taken on that leg
+    /// 14. `[]` The config account (seeds: `b"config"`)
+    SettleAuction,
 }
 
 //methods for decoding instruction data
@@ -48,12 +301,146 @@ impl EscrowInstruction {
 
         //looks at first byte (tag) to decide what to do with the remaining slice (rest)
         Ok(match tag {
-            0 => Self::InitEscrow {
-                amount: Self::unpack_amount(rest)?,
+            0 => {
+                let (amount, rest) = Self::unpack_u64(rest)?;
+                let (unlock_time, rest) = Self::unpack_u64(rest)?;
+                let (time_out, mut rest) = Self::unpack_u64(rest)?;
+                //the optional fields follow in any order, each behind its own tag byte
+                let mut taker = None;
+                let mut auction = None;
+                let mut nonce = None;
+                let mut deposit = None;
+                let mut delegated_amount = None;
+                while let Some((field, field_data)) = rest.split_first() {
+                    rest = match field {
+                        1 | 2 if auction.is_some() => return Err(InvalidInstruction.into()),
+                        1 => {
+                            let (start_amount, field_data) = Self::unpack_u64(field_data)?;
+                            let (start_time, field_data) = Self::unpack_u64(field_data)?;
+                            let (end_time, field_data) = Self::unpack_u64(field_data)?;
+                            let (step, field_data) = Self::unpack_u64(field_data)?;
+                            auction = Some(Auction::Dutch(DutchAuction {
+                                start_amount,
+                                start_time,
+                                end_time,
+                                step,
+                            }));
+                            field_data
+                        }
+                        2 => {
+                            let (min_increment, field_data) = Self::unpack_u64(field_data)?;
+                            auction = Some(Auction::English(EnglishAuction { min_increment }));
+                            field_data
+                        }
+                        3 if nonce.is_none() => {
+                            let (value, field_data) = Self::unpack_u64(field_data)?;
+                            nonce = Some(value);
+                            field_data
+                        }
+                        4 if deposit.is_none() => {
+                            let (value, field_data) = Self::unpack_u64(field_data)?;
+                            deposit = Some(value);
+                            field_data
+                        }
+                        5 if delegated_amount.is_none() => {
+                            let (value, field_data) = Self::unpack_u64(field_data)?;
+                            delegated_amount = Some(value);
+                            field_data
+                        }
+                        6 if taker.is_none() => {
+                            let (value, field_data) = Self::unpack_pubkey(field_data)?;
+                            taker = Some(value);
+                            field_data
+                        }
+                        _ => return Err(InvalidInstruction.into()),
+                    };
+                }
+                Self::InitEscrow {
+                    amount,
+                    unlock_time,
+                    time_out,
+                    taker,
+                    auction,
+                    nonce,
+                    deposit,
+                    delegated_amount,
+                }
+            }
+            1 => {
+                let (amount, rest) = Self::unpack_u64(rest)?;
+                //both trailing fields are optional, a lone flag byte is shorter than a price limit
+                let (max_requested_amount, rest) = match rest.len() {
+                    0 | 1 => (None, rest),
+                    _ => {
+                        let (max_requested_amount, rest) = Self::unpack_u64(rest)?;
+                        (Some(max_requested_amount), rest)
+                    }
+                };
+                let create_receive_account = match rest {
+                    [] => false,
+                    [1] => true,
+                    _ => return Err(InvalidInstruction.into()),
+                };
+                Self::Exchange {
+                    amount,
+                    max_requested_amount,
+                    create_receive_account,
+                }
+            }
+            2 => Self::Cancel,
+            3 => Self::MigrateEscrow,
+            4 | 5 => {
+                let (admin, rest) = Self::unpack_pubkey(rest)?;
+                let (fee_owner, rest) = Self::unpack_pubkey(rest)?;
+                let (offered_bps, rest) = Self::unpack_u16(rest)?;
+                let (requested_bps, rest) = Self::unpack_u16(rest)?;
+                let (features, rest) = Self::unpack_u64(rest)?;
+                let (expire_bounty, _rest) = Self::unpack_u64(rest)?;
+                let fee = Fee {
+                    offered_bps,
+                    requested_bps,
+                };
+                if *tag == 4 {
+                    Self::InitializeConfig {
+                        admin,
+                        fee_owner,
+                        fee,
+                        features,
+                        expire_bounty,
+                    }
+                } else {
+                    Self::UpdateConfig {
+                        admin,
+                        fee_owner,
+                        fee,
+                        features,
+                        expire_bounty,
+                    }
+                }
+            }
+            6 => Self::SetPaused {
+                paused: match rest.first() {
+                    Some(0) => false,
+                    Some(1) => true,
+                    _ => return Err(InvalidInstruction.into()),
+                },
             },
-            1 => Self::Exchange {
+            7 => Self::Expire,
+            8 => {
+                let (amount, rest) = Self::unpack_u64(rest)?;
+                let deadlines = match rest.is_empty() {
+                    true => None,
+                    false => {
+                        let (unlock_time, rest) = Self::unpack_u64(rest)?;
+                        Some((unlock_time, Self::unpack_amount(rest)?))
+                    }
+                };
+                Self::UpdateExpectedAmount { amount, deadlines }
+            }
+            9 => Self::PlaceBid {
                 amount: Self::unpack_amount(rest)?,
             },
+            10 => Self::SettleAuction,
             _ => return Err(InvalidInstruction.into()),
         })
     }
@@ -67,4 +454,646 @@ impl EscrowInstruction {
             .ok_or(InvalidInstruction)?;
         Ok(amount)
     }
+
+    //same as unpack_amount, but also hands back whatever comes after the u64 so several values can be read in a row
+    fn unpack_u64(input: &[u8]) -> Result<(u64, &[u8]), ProgramError> {
+        let value = Self::unpack_amount(input)?;
+        Ok((value, &input[8..]))
+    }
+
+    fn unpack_u16(input: &[u8]) -> Result<(u16, &[u8]), ProgramError> {
+        let value = input
+            .get(..2)
+            .and_then(|slice| slice.try_into().ok())
+            .map(u16::from_le_bytes)
+            .ok_or(InvalidInstruction)?;
+        Ok((value, &input[2..]))
+    }
+
+    fn unpack_pubkey(input: &[u8]) -> Result<(Pubkey, &[u8]), ProgramError> {
+        let pubkey = input
+            .get(..32)
+            .and_then(|slice| slice.try_into().ok())
+            .map(Pubkey::new_from_array)
+            .ok_or(InvalidInstruction)?;
+        Ok((pubkey, &input[32..]))
+    }
+
+    /// Packs a [EscrowInstruction](enum.EscrowInstruction.html) into a byte buffer, the inverse of `unpack`.
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(1 + 2 * 32 + 2 * 2 + 2 * 8);
+        match self {
+            Self::InitEscrow {
+                amount,
+                unlock_time,
+                time_out,
+                taker,
+                auction,
+                nonce,
+                deposit,
+                delegated_amount,
+            } => {
+                buf.push(0);
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.extend_from_slice(&unlock_time.to_le_bytes());
+                buf.extend_from_slice(&time_out.to_le_bytes());
+                if let Some(taker) = taker {
+                    buf.push(6);
+                    buf.extend_from_slice(taker.as_ref());
+                }
+                match auction {
+                    Some(Auction::Dutch(auction)) => {
+                        buf.push(1);
+                        buf.extend_from_slice(&auction.start_amount.to_le_bytes());
+                        buf.extend_from_slice(&auction.start_time.to_le_bytes());
+                        buf.extend_from_slice(&auction.end_time.to_le_bytes());
+                        buf.extend_from_slice(&auction.step.to_le_bytes());
+                    }
+                    Some(Auction::English(auction)) => {
+                        buf.push(2);
+                        buf.extend_from_slice(&auction.min_increment.to_le_bytes());
+                    }
+                    None => {}
+                }
+                if let Some(nonce) = nonce {
+                    buf.push(3);
+                    buf.extend_from_slice(&nonce.to_le_bytes());
+                }
+                if let Some(deposit) = deposit {
+                    buf.push(4);
+                    buf.extend_from_slice(&deposit.to_le_bytes());
+                }
+                if let Some(delegated_amount) = delegated_amount {
+                    buf.push(5);
+                    buf.extend_from_slice(&delegated_amount.to_le_bytes());
+                }
+            }
+            Self::Exchange {
+                amount,
+                max_requested_amount,
+                create_receive_account,
+            } => {
+                buf.push(1);
+                buf.extend_from_slice(&amount.to_le_bytes());
+                if let Some(max_requested_amount) = max_requested_amount {
+                    buf.extend_from_slice(&max_requested_amount.to_le_bytes());
+                }
+                if *create_receive_account {
+                    buf.push(1);
+                }
+            }
+            Self::Cancel => buf.push(2),
+            Self::MigrateEscrow => buf.push(3),
+            Self::InitializeConfig {
+                admin,
+                fee_owner,
+                fee,
+                features,
+                expire_bounty,
+            } => {
+                buf.push(4);
+                Self::pack_config(&mut buf, admin, fee_owner, fee, *features, *expire_bounty);
+            }
+            Self::UpdateConfig {
+                admin,
+                fee_owner,
+                fee,
+                features,
+                expire_bounty,
+            } => {
+                buf.push(5);
+                Self::pack_config(&mut buf, admin, fee_owner, fee, *features, *expire_bounty);
+            }
+            Self::SetPaused { paused } => {
+                buf.push(6);
+                buf.push(*paused as u8);
+            }
+            Self::Expire => buf.push(7),
+            Self::UpdateExpectedAmount { amount, deadlines } => {
+                buf.push(8);
+                buf.extend_from_slice(&amount.to_le_bytes());
+                if let Some((unlock_time, time_out)) = deadlines {
+                    buf.extend_from_slice(&unlock_time.to_le_bytes());
+                    buf.extend_from_slice(&time_out.to_le_bytes());
+                }
+            }
+            Self::PlaceBid { amount } => {
+                buf.push(9);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::SettleAuction => buf.push(10),
+        }
+        buf
+    }
+
+    //InitializeConfig and UpdateConfig carry the same settings
+    fn pack_config(
+        buf: &mut Vec<u8>,
+        admin: &Pubkey,
+        fee_owner: &Pubkey,
+        fee: &Fee,
+        features: u64,
+        expire_bounty: u64,
+    ) {
+        buf.extend_from_slice(admin.as_ref());
+        buf.extend_from_slice(fee_owner.as_ref());
+        buf.extend_from_slice(&fee.offered_bps.to_le_bytes());
+        buf.extend_from_slice(&fee.requested_bps.to_le_bytes());
+        buf.extend_from_slice(&features.to_le_bytes());
+        buf.extend_from_slice(&expire_bounty.to_le_bytes());
+    }
+}
+
+//helpers for clients, each one lists the accounts in exactly the order documented on EscrowInstruction
+
+//the PDA allowed to move an escrow's offered tokens: the escrow's own one, or the initializer's delegate for a delegated escrow
+fn offered_tokens_authority(
+    program_id: &Pubkey,
+    initializer_pubkey: &Pubkey,
+    escrow_account_pubkey: &Pubkey,
+    delegated: bool,
+) -> Pubkey {
+    match delegated {
+        true => Escrow::find_delegate_address(initializer_pubkey, program_id).0,
+        false => Escrow::find_authority_address(escrow_account_pubkey, program_id).0,
+    }
+}
+
+/// The optional parts of an `InitEscrow` instruction, `InitEscrowOptions::default()` opens a plain fixed price trade.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InitEscrowOptions {
+    /// Makes the trade private to this account
+    pub taker: Option<Pubkey>,
+    /// Sells by auction (see `EscrowInstruction::InitEscrow`)
+    pub auction: Option<Auction>,
+    /// English auctions only: the account that holds the bids and the requested token's program
+    pub bid_token_account: Option<(Pubkey, Pubkey)>,
+    /// Has the program create the escrow account, `escrow_account_pubkey` must then be `Escrow::find_address`
+    pub nonce: Option<u64>,
+    /// The amount and the initializer's token account it's paid from. Has the program create the vault token account,
+    /// `temp_token_account_pubkey` must then be `Escrow::find_vault_address`
+    pub deposit: Option<(u64, Pubkey)>,
+    /// Offers the tokens straight out of `temp_token_account_pubkey`, the initializer's own account
+    pub delegated_amount: Option<u64>,
+}
+
+/// Creates an `InitEscrow` instruction, see `InitEscrowOptions` for the optional parts.
+#[allow(clippy::too_many_arguments)]
+pub fn init_escrow(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    initializer_pubkey: &Pubkey,
+    temp_token_account_pubkey: &Pubkey,
+    token_to_receive_account_pubkey: &Pubkey,
+    escrow_account_pubkey: &Pubkey,
+    offered_mint_pubkey: &Pubkey,
+    requested_mint_pubkey: &Pubkey,
+    amount: u64,
+    unlock_time: u64,
+    time_out: u64,
+    options: InitEscrowOptions,
+) -> Instruction {
+    let InitEscrowOptions {
+        taker,
+        auction,
+        bid_token_account,
+        nonce,
+        deposit,
+        delegated_amount,
+    } = options;
+    let data = EscrowInstruction::InitEscrow {
+        amount,
+        unlock_time,
+        time_out,
+        taker,
+        auction,
+        nonce,
+        deposit: deposit.map(|(deposit, _)| deposit),
+        delegated_amount,
+    }
+    .pack();
+
+    let mut accounts = vec![
+        AccountMeta {
+            pubkey: *initializer_pubkey,
+            is_signer: true,
+            is_writable: nonce.is_some() || deposit.is_some(),
+        },
+        AccountMeta::new(*temp_token_account_pubkey, false),
+        AccountMeta::new_readonly(*token_to_receive_account_pubkey, false),
+        AccountMeta::new(*escrow_account_pubkey, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(Config::find_address(program_id).0, false),
+        AccountMeta::new_readonly(*offered_mint_pubkey, false),
+        AccountMeta::new_readonly(*requested_mint_pubkey, false),
+    ];
+    if let Some((bid_token_account_pubkey, requested_token_program_id)) = bid_token_account {
+        accounts.push(AccountMeta::new(bid_token_account_pubkey, false));
+        accounts.push(AccountMeta::new_readonly(requested_token_program_id, false));
+    }
+    if let Some((_, initializers_sending_token_account_pubkey)) = deposit {
+        accounts.push(AccountMeta::new(
+            initializers_sending_token_account_pubkey,
+            false,
+        ));
+    }
+    if delegated_amount.is_some() {
+        accounts.push(AccountMeta::new_readonly(
+            Escrow::find_delegate_address(initializer_pubkey, program_id).0,
+            false,
+        ));
+    }
+    if nonce.is_some() || deposit.is_some() {
+        accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+    }
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    }
+}
+
+/// The optional parts of an `Exchange` instruction, all off by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExchangeOptions {
+    /// Makes the exchange fail if the price went up since the taker looked at it
+    pub max_requested_amount: Option<u64>,
+    /// Whether the escrow was set up with a `delegated_amount`
+    pub delegated: bool,
+    /// Creates `takers_token_to_receive_account_pubkey`, the taker's associated token account, first if needed
+    pub create_receive_account: bool,
+}
+
+/// Creates an `Exchange` instruction taking `amount` of the offered tokens, the protocol fee goes to the two fee token accounts.
+/// See `ExchangeOptions` for the optional parts.
+#[allow(clippy::too_many_arguments)]
+pub fn exchange(
+    program_id: &Pubkey,
+    offered_token_program_id: &Pubkey,
+    requested_token_program_id: &Pubkey,
+    taker_pubkey: &Pubkey,
+    takers_sending_token_account_pubkey: &Pubkey,
+    takers_token_to_receive_account_pubkey: &Pubkey,
+    temp_token_account_pubkey: &Pubkey,
+    initializer_pubkey: &Pubkey,
+    initializers_token_to_receive_account_pubkey: &Pubkey,
+    escrow_account_pubkey: &Pubkey,
+    offered_mint_pubkey: &Pubkey,
+    requested_mint_pubkey: &Pubkey,
+    offered_fee_account_pubkey: &Pubkey,
+    requested_fee_account_pubkey: &Pubkey,
+    amount: u64,
+    options: ExchangeOptions,
+) -> Instruction {
+    let ExchangeOptions {
+        max_requested_amount,
+        delegated,
+        create_receive_account,
+    } = options;
+    let data = EscrowInstruction::Exchange {
+        amount,
+        max_requested_amount,
+        create_receive_account,
+    }
+    .pack();
+    let pda = offered_tokens_authority(
+        program_id,
+        initializer_pubkey,
+        escrow_account_pubkey,
+        delegated,
+    );
+
+    let mut accounts = vec![
+        AccountMeta {
+            pubkey: *taker_pubkey,
+            is_signer: true,
+            is_writable: create_receive_account,
+        },
+        AccountMeta::new(*takers_sending_token_account_pubkey, false),
+        AccountMeta::new(*takers_token_to_receive_account_pubkey, false),
+        AccountMeta::new(*temp_token_account_pubkey, false),
+        AccountMeta::new(*initializer_pubkey, false),
+        AccountMeta::new(*initializers_token_to_receive_account_pubkey, false),
+        AccountMeta::new(*escrow_account_pubkey, false),
+        AccountMeta::new_readonly(*offered_token_program_id, false),
+        AccountMeta::new_readonly(pda, false),
+        AccountMeta::new(*offered_fee_account_pubkey, false),
+        AccountMeta::new(*requested_fee_account_pubkey, false),
+        AccountMeta::new_readonly(Config::find_address(program_id).0, false),
+        AccountMeta::new_readonly(*requested_token_program_id, false),
+        AccountMeta::new(*offered_mint_pubkey, false),
+        AccountMeta::new_readonly(*requested_mint_pubkey, false),
+    ];
+    if create_receive_account {
+        accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+        accounts.push(AccountMeta::new_readonly(
+            spl_associated_token_account::id(),
+            false,
+        ));
+    }
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    }
+}
+
+/// Creates a `Cancel` instruction refunding the offered tokens to `initializers_token_to_refund_account_pubkey`.
+/// `delegated` tells whether the escrow was set up with a `delegated_amount`.
+#[allow(clippy::too_many_arguments)]
+pub fn cancel(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    initializer_pubkey: &Pubkey,
+    temp_token_account_pubkey: &Pubkey,
+    initializers_token_to_refund_account_pubkey: &Pubkey,
+    escrow_account_pubkey: &Pubkey,
+    offered_mint_pubkey: &Pubkey,
+    delegated: bool,
+) -> Instruction {
+    let data = EscrowInstruction::Cancel.pack();
+    let pda = offered_tokens_authority(
+        program_id,
+        initializer_pubkey,
+        escrow_account_pubkey,
+        delegated,
+    );
+
+    let accounts = vec![
+        AccountMeta::new(*initializer_pubkey, true),
+        AccountMeta::new(*temp_token_account_pubkey, false),
+        AccountMeta::new(*initializers_token_to_refund_account_pubkey, false),
+        AccountMeta::new(*escrow_account_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(pda, false),
+        AccountMeta::new(*offered_mint_pubkey, false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    }
+}
+
+/// Creates a `MigrateEscrow` instruction.
+pub fn migrate_escrow(
+    program_id: &Pubkey,
+    payer_pubkey: &Pubkey,
+    escrow_account_pubkey: &Pubkey,
+    temp_token_account_pubkey: &Pubkey,
+    initializers_token_to_receive_account_pubkey: &Pubkey,
+    offered_mint_pubkey: &Pubkey,
+    requested_mint_pubkey: &Pubkey,
+) -> Instruction {
+    let data = EscrowInstruction::MigrateEscrow.pack();
+    let (legacy_pda, _bump_seed) =
+        Pubkey::find_program_address(&[Escrow::AUTHORITY_SEED], program_id);
+
+    let accounts = vec![
+        AccountMeta::new(*payer_pubkey, true),
+        AccountMeta::new(*escrow_account_pubkey, false),
+        AccountMeta::new(*temp_token_account_pubkey, false),
+        AccountMeta::new_readonly(*initializers_token_to_receive_account_pubkey, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(legacy_pda, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(*offered_mint_pubkey, false),
+        AccountMeta::new_readonly(*requested_mint_pubkey, false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    }
+}
+
+/// Creates an `InitializeConfig` instruction.
+pub fn initialize_config(
+    program_id: &Pubkey,
+    upgrade_authority_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    fee_owner_pubkey: &Pubkey,
+    fee: Fee,
+    features: u64,
+    expire_bounty: u64,
+) -> Instruction {
+    let data = EscrowInstruction::InitializeConfig {
+        admin: *admin_pubkey,
+        fee_owner: *fee_owner_pubkey,
+        fee,
+        features,
+        expire_bounty,
+    }
+    .pack();
+    let (program_data, _bump_seed) =
+        Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+
+    let accounts = vec![
+        AccountMeta::new(*upgrade_authority_pubkey, true),
+        AccountMeta::new(Config::find_address(program_id).0, false),
+        AccountMeta::new_readonly(program_data, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    }
+}
+
+/// Creates an `UpdateConfig` instruction.
+pub fn update_config(
+    program_id: &Pubkey,
+    admin_pubkey: &Pubkey,
+    new_admin_pubkey: &Pubkey,
+    fee_owner_pubkey: &Pubkey,
+    fee: Fee,
+    features: u64,
+    expire_bounty: u64,
+) -> Instruction {
+    let data = EscrowInstruction::UpdateConfig {
+        admin: *new_admin_pubkey,
+        fee_owner: *fee_owner_pubkey,
+        fee,
+        features,
+        expire_bounty,
+    }
+    .pack();
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*admin_pubkey, true),
+        AccountMeta::new(Config::find_address(program_id).0, false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    }
+}
+
+/// Creates a `SetPaused` instruction
+pub fn set_paused(program_id: &Pubkey, admin_pubkey: &Pubkey, paused: bool) -> Instruction {
+    let data = EscrowInstruction::SetPaused { paused }.pack();
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*admin_pubkey, true),
+        AccountMeta::new(Config::find_address(program_id).0, false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    }
+}
+
+/// Creates an `Expire` instruction refunding a timed out escrow to `initializers_token_to_refund_account_pubkey`.
+/// `delegated` tells whether the escrow was set up with a `delegated_amount`.
+#[allow(clippy::too_many_arguments)]
+pub fn expire(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    caller_pubkey: &Pubkey,
+    temp_token_account_pubkey: &Pubkey,
+    initializers_token_to_refund_account_pubkey: &Pubkey,
+    initializer_pubkey: &Pubkey,
+    escrow_account_pubkey: &Pubkey,
+    offered_mint_pubkey: &Pubkey,
+    delegated: bool,
+) -> Instruction {
+    let data = EscrowInstruction::Expire.pack();
+    let pda = offered_tokens_authority(
+        program_id,
+        initializer_pubkey,
+        escrow_account_pubkey,
+        delegated,
+    );
+
+    let accounts = vec![
+        AccountMeta::new(*caller_pubkey, true),
+        AccountMeta::new(*temp_token_account_pubkey, false),
+        AccountMeta::new(*initializers_token_to_refund_account_pubkey, false),
+        AccountMeta::new(*initializer_pubkey, false),
+        AccountMeta::new(*escrow_account_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(pda, false),
+        AccountMeta::new(*offered_mint_pubkey, false),
+        AccountMeta::new_readonly(Config::find_address(program_id).0, false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    }
+}
+
+/// Creates an `UpdateExpectedAmount` instruction, passing `deadlines` also moves the trade's `(unlock_time, time_out)`.
+pub fn update_expected_amount(
+    program_id: &Pubkey,
+    initializer_pubkey: &Pubkey,
+    escrow_account_pubkey: &Pubkey,
+    amount: u64,
+    deadlines: Option<(u64, u64)>,
+) -> Instruction {
+    let data = EscrowInstruction::UpdateExpectedAmount { amount, deadlines }.pack();
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*initializer_pubkey, true),
+        AccountMeta::new(*escrow_account_pubkey, false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    }
+}
+
+/// Creates a `PlaceBid` instruction, `previous_bidders_refund_account_pubkey` is ignored for the first bid.
+#[allow(clippy::too_many_arguments)]
+pub fn place_bid(
+    program_id: &Pubkey,
+    requested_token_program_id: &Pubkey,
+    bidder_pubkey: &Pubkey,
+    bidders_sending_token_account_pubkey: &Pubkey,
+    escrow_account_pubkey: &Pubkey,
+    bid_token_account_pubkey: &Pubkey,
+    previous_bidders_refund_account_pubkey: &Pubkey,
+    requested_mint_pubkey: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let data = EscrowInstruction::PlaceBid { amount }.pack();
+    let (pda, _bump_seed) = Escrow::find_authority_address(escrow_account_pubkey, program_id);
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*bidder_pubkey, true),
+        AccountMeta::new(*bidders_sending_token_account_pubkey, false),
+        AccountMeta::new(*escrow_account_pubkey, false),
+        AccountMeta::new(*bid_token_account_pubkey, false),
+        AccountMeta::new(*previous_bidders_refund_account_pubkey, false),
+        AccountMeta::new_readonly(*requested_token_program_id, false),
+        AccountMeta::new_readonly(*requested_mint_pubkey, false),
+        AccountMeta::new_readonly(pda, false),
+        AccountMeta::new_readonly(Config::find_address(program_id).0, false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    }
+}
+
+/// Creates a `SettleAuction` instruction.
+#[allow(clippy::too_many_arguments)]
+pub fn settle_auction(
+    program_id: &Pubkey,
+    offered_token_program_id: &Pubkey,
+    requested_token_program_id: &Pubkey,
+    caller_pubkey: &Pubkey,
+    temp_token_account_pubkey: &Pubkey,
+    bid_token_account_pubkey: &Pubkey,
+    winners_token_to_receive_account_pubkey: &Pubkey,
+    initializers_token_to_receive_account_pubkey: &Pubkey,
+    initializer_pubkey: &Pubkey,
+    escrow_account_pubkey: &Pubkey,
+    offered_mint_pubkey: &Pubkey,
+    requested_mint_pubkey: &Pubkey,
+    offered_fee_account_pubkey: &Pubkey,
+    requested_fee_account_pubkey: &Pubkey,
+) -> Instruction {
+    let data = EscrowInstruction::SettleAuction.pack();
+    let (pda, _bump_seed) = Escrow::find_authority_address(escrow_account_pubkey, program_id);
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*caller_pubkey, true),
+        AccountMeta::new(*temp_token_account_pubkey, false),
+        AccountMeta::new(*bid_token_account_pubkey, false),
+        AccountMeta::new(*winners_token_to_receive_account_pubkey, false),
+        AccountMeta::new(*initializers_token_to_receive_account_pubkey, false),
+        AccountMeta::new(*initializer_pubkey, false),
+        AccountMeta::new(*escrow_account_pubkey, false),
+        AccountMeta::new_readonly(*offered_token_program_id, false),
+        AccountMeta::new_readonly(*requested_token_program_id, false),
+        AccountMeta::new_readonly(pda, false),
+        AccountMeta::new(*offered_mint_pubkey, false),
+        AccountMeta::new(*requested_mint_pubkey, false),
+        AccountMeta::new(*offered_fee_account_pubkey, false),
+        AccountMeta::new(*requested_fee_account_pubkey, false),
+        AccountMeta::new_readonly(Config::find_address(program_id).0, false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    }
 }
@@ -3,7 +3,12 @@
 #[cfg(not(feature = "no-entrypoint"))]
 pub mod entrypoint;
 
+pub mod auction;
 pub mod error;
+pub mod event;
+pub mod fee;
 pub mod instruction;
+pub mod native;
 pub mod processor;
 pub mod state;
+pub mod token;
@@ -0,0 +1,81 @@
+/*Native SOL --> legs of an escrow traded in plain lamports instead of a token, so nobody has to wrap SOL first
+
+An escrow offering SOL keeps the lamports in a vault account owned by this program (at `Escrow::find_vault_address`),
+on top of the vault's rent exemption. The program may debit accounts it owns directly, so paying out needs no CPI.
+An escrow requesting SOL has the taker pay it with a system transfer straight to the initializer's wallet.
+*/
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    program::invoke,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_instruction, system_program,
+    sysvar::{rent::Rent, Sysvar},
+};
+
+use crate::{error::EscrowError, token};
+
+/// Stands in for the mint of a native SOL leg: escrows record it as that leg's mint and clients pass the system program
+/// wherever a mint (and its token program) is expected. Not to be confused with the wrapped SOL mint, which is a token
+pub const MINT: Pubkey = system_program::ID;
+
+/// Decimals recorded for native SOL (1 SOL is 10^9 lamports)
+pub const DECIMALS: u8 = 9;
+
+/// Whether the mint stands for native SOL
+pub fn is_native(mint: &Pubkey) -> bool {
+    *mint == MINT
+}
+
+/// Makes sure the program passed for a leg can move it: the system program for native SOL, otherwise the token
+/// program owning the mint
+pub fn check_leg_program(program: &AccountInfo, mint: &AccountInfo) -> ProgramResult {
+    if is_native(mint.key) {
+        if *program.key != system_program::ID {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        return Ok(());
+    }
+    token::check_token_program(program)?;
+    if mint.owner != program.key {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    Ok(())
+}
+
+/// Lamports a vault of this program holds on top of its rent exemption
+pub fn balance(vault: &AccountInfo, program_id: &Pubkey) -> Result<u64, ProgramError> {
+    if vault.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    Ok(vault
+        .lamports()
+        .saturating_sub(Rent::get()?.minimum_balance(vault.data_len())))
+}
+
+/// Pays lamports out of a wallet that signed the transaction
+pub fn pay<'a>(
+    system_program: &AccountInfo<'a>,
+    from: &AccountInfo<'a>,
+    to: &AccountInfo<'a>,
+    amount: u64,
+) -> ProgramResult {
+    invoke(
+        &system_instruction::transfer(from.key, to.key, amount),
+        &[from.clone(), to.clone(), system_program.clone()],
+    )
+}
+
+/// Moves lamports out of an account of this program (e.g. a vault), the runtime lets a program debit the accounts it owns
+pub fn withdraw(vault: &AccountInfo, to: &AccountInfo, amount: u64) -> ProgramResult {
+    **vault.lamports.borrow_mut() = vault
+        .lamports()
+        .checked_sub(amount)
+        .ok_or(ProgramError::InsufficientFunds)?;
+    **to.lamports.borrow_mut() = to
+        .lamports()
+        .checked_add(amount)
+        .ok_or(EscrowError::AmountOverflow)?;
+    Ok(())
+}
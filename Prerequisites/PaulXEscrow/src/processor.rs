@@ -2,17 +2,30 @@
 
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
+    bpf_loader_upgradeable,
+    clock::Clock,
     entrypoint::ProgramResult,
     msg,
     program::{invoke, invoke_signed},
     program_error::ProgramError,
+    program_option::COption,
     program_pack::{IsInitialized, Pack},
     pubkey::Pubkey,
+    system_instruction, system_program,
     sysvar::{rent::Rent, Sysvar},
 }; //we saw this also in entrypoint.rs
 
-use crate::{error::EscrowError, instruction::EscrowInstruction, state::Escrow}; //getting from instruction.rs
-use spl_token::state::Account as TokenAccount;
+use crate::{
+    auction::{Auction, Bid},
+    error::EscrowError,
+    event::EscrowEvent,
+    fee::Fee,
+    instruction::EscrowInstruction,
+    native,
+    state::{Config, Escrow},
+    token,
+}; //getting from instruction.rs
+use arrayref::{array_ref, array_refs};
 
 pub struct Processor;
 impl Processor {
@@ -23,23 +36,178 @@ impl Processor {
     ) -> ProgramResult {
         let instruction = EscrowInstruction::unpack(instruction_data)?; //reference to slice holding instruction_data (from entrypoint.rs) goes into the unpack functinon (instruction.rs)
 
+        //while the admin has the program paused no trade can be opened or taken, cancelling and refunding keep working
         match instruction {
-            EscrowInstruction::InitEscrow { amount } => {
+            EscrowInstruction::InitEscrow { .. } => {
+                Self::check_not_paused(accounts, 6, program_id)?
+            }
+            EscrowInstruction::Exchange { .. } => Self::check_not_paused(accounts, 11, program_id)?,
+            EscrowInstruction::PlaceBid { .. } => Self::check_not_paused(accounts, 8, program_id)?,
+            _ => {}
+        }
+
+        match instruction {
+            EscrowInstruction::InitEscrow {
+                amount,
+                unlock_time,
+                time_out,
+                taker,
+                auction,
+                nonce,
+                deposit,
+                delegated_amount,
+            } => {
                 msg!("Instruction: InitEscrow");
-                Self::process_init_escrow(accounts, amount, program_id)
+                Self::process_init_escrow(
+                    accounts,
+                    amount,
+                    unlock_time,
+                    time_out,
+                    taker,
+                    auction,
+                    nonce,
+                    deposit,
+                    delegated_amount,
+                    program_id,
+                )
             }
-            EscrowInstruction::Exchange { amount } => {
+            EscrowInstruction::Exchange {
+                amount,
+                max_requested_amount,
+                create_receive_account,
+            } => {
                 msg!("Instruction: Exchange");
-                Self::process_exchange(accounts, amount, program_id)
+                Self::process_exchange(
+                    accounts,
+                    amount,
+                    max_requested_amount,
+                    create_receive_account,
+                    program_id,
+                )
+            }
+            EscrowInstruction::Cancel => {
+                msg!("Instruction: Cancel");
+                Self::process_cancel(accounts, program_id)
+            }
+            EscrowInstruction::MigrateEscrow => {
+                msg!("Instruction: MigrateEscrow");
+                Self::process_migrate_escrow(accounts, program_id)
+            }
+            EscrowInstruction::InitializeConfig {
+                admin,
+                fee_owner,
+                fee,
+                features,
+                expire_bounty,
+            } => {
+                msg!("Instruction: InitializeConfig");
+                Self::process_initialize_config(
+                    accounts,
+                    admin,
+                    fee_owner,
+                    fee,
+                    features,
+                    expire_bounty,
+                    program_id,
+                )
+            }
+            EscrowInstruction::UpdateConfig {
+                admin,
+                fee_owner,
+                fee,
+                features,
+                expire_bounty,
+            } => {
+                msg!("Instruction: UpdateConfig");
+                Self::process_update_config(
+                    accounts,
+                    admin,
+                    fee_owner,
+                    fee,
+                    features,
+                    expire_bounty,
+                    program_id,
+                )
             }
+            EscrowInstruction::SetPaused { paused } => {
+                msg!("Instruction: SetPaused");
+                Self::process_set_paused(accounts, paused, program_id)
+            }
+            EscrowInstruction::Expire => {
+                msg!("Instruction: Expire");
+                Self::process_expire(accounts, program_id)
+            }
+            EscrowInstruction::UpdateExpectedAmount { amount, deadlines } => {
+                msg!("Instruction: UpdateExpectedAmount");
+                Self::process_update_expected_amount(accounts, amount, deadlines, program_id)
+            }
+            EscrowInstruction::PlaceBid { amount } => {
+                msg!("Instruction: PlaceBid");
+                Self::process_place_bid(accounts, amount, program_id)
+            }
+            EscrowInstruction::SettleAuction => {
+                msg!("Instruction: SettleAuction");
+                Self::process_settle_auction(accounts, program_id)
+            }
+        }
+    }
+
+    //`config_index` is the position of the config account in the instruction's account list
+    fn check_not_paused(
+        accounts: &[AccountInfo],
+        config_index: usize,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let config_account = accounts
+            .get(config_index)
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        if Self::load_config(config_account, program_id)?.paused {
+            return Err(EscrowError::ProgramPaused.into());
         }
+        Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn process_init_escrow(
         accounts: &[AccountInfo],
         amount: u64,
+        unlock_time: u64,
+        time_out: u64,
+        taker: Option<Pubkey>,
+        auction: Option<Auction>,
+        nonce: Option<u64>,
+        deposit: Option<u64>,
+        delegated_amount: Option<u64>,
         program_id: &Pubkey,
     ) -> ProgramResult {
+        //a trade that times out before it unlocks could never be taken
+        if unlock_time >= time_out {
+            return Err(EscrowError::InvalidInstruction.into());
+        }
+        //an empty vault or approval would offer nothing
+        if deposit == Some(0) || delegated_amount == Some(0) {
+            return Err(EscrowError::InvalidInstruction.into());
+        }
+        //the offered tokens are either deposited into a vault or stay in Alice's account, not both
+        if deposit.is_some() && delegated_amount.is_some() {
+            return Err(EscrowError::InvalidInstruction.into());
+        }
+        match auction {
+            //the price of a Dutch auction has to fall from its start amount down to the floor
+            Some(Auction::Dutch(auction)) if !auction.is_valid(amount) => {
+                return Err(EscrowError::InvalidInstruction.into());
+            }
+            //an English auction is open to every bidder
+            Some(Auction::English(_)) if taker.is_some() => {
+                return Err(EscrowError::InvalidInstruction.into());
+            }
+            //and its winner must be sure to get the tokens, so they have to be locked up in the escrow
+            Some(Auction::English(_)) if delegated_amount.is_some() => {
+                return Err(EscrowError::InvalidInstruction.into());
+            }
+            _ => {}
+        }
+
         let account_info_iter = &mut accounts.iter(); //mutable so we can take elements out of it
         let initializer = next_account_info(account_info_iter)?; //first account we expect is escrow's initializer
 
@@ -49,16 +217,111 @@ impl Processor {
         }
 
         //no need to check if owned by token program, since we will be transfering ownership to PDA (ownership transfer will automatically fail if not owned by token program. No check explicity needed!)
+        //with a deposit this is the vault the program creates below instead
         let temp_token_account = next_account_info(account_info_iter)?; //needs to be writable but no need to check, transaction will fail automatically
 
         let token_to_receive_account = next_account_info(account_info_iter)?;
-        if *token_to_receive_account.owner != spl_token::id() {
-            //check that this is actually owned by the token program (changes aren't being made, so we need explicit check). If we didnt have this check, instead of Alice's transaction failing, Bob's would fail!
-            return Err(ProgramError::IncorrectProgramId);
-        }
-
         let escrow_account = next_account_info(account_info_iter)?;
         let rent = &Rent::from_account_info(next_account_info(account_info_iter)?)?;
+        let token_program = next_account_info(account_info_iter)?;
+        next_account_info(account_info_iter)?; //the config account, already checked for a pause in process
+        let offered_mint_account = next_account_info(account_info_iter)?;
+        let requested_mint_account = next_account_info(account_info_iter)?;
+
+        //either leg may be native SOL, the system program then stands in for its mint
+        let offered_native = native::is_native(offered_mint_account.key);
+        let requested_native = native::is_native(requested_mint_account.key);
+        //SOL can only be offered from a vault the program creates, and bids and approvals only work with tokens
+        if offered_native && (deposit.is_none() || delegated_amount.is_some()) {
+            return Err(EscrowError::InvalidInstruction.into());
+        }
+        if (offered_native || requested_native) && matches!(auction, Some(Auction::English(_))) {
+            return Err(EscrowError::InvalidInstruction.into());
+        }
+        native::check_leg_program(token_program, offered_mint_account)?;
+
+        //remember which tokens are traded so the taker can't swap in accounts of another mint later
+        let requested_mint = if requested_native {
+            //SOL is paid straight into Alice's wallet
+            if token_to_receive_account.key != initializer.key {
+                return Err(ProgramError::InvalidAccountData);
+            }
+            native::MINT
+        } else {
+            if !token::is_token_program(token_to_receive_account.owner) {
+                //check that this is actually owned by the token program (changes aren't being made, so we need explicit check). If we didnt have this check, instead of Alice's transaction failing, Bob's would fail!
+                return Err(ProgramError::IncorrectProgramId);
+            }
+            token::unpack_account(token_to_receive_account)?.mint
+        };
+
+        //the decimals are recorded so every later transfer_checked proves the mints still are what Alice priced the trade in
+        //a vault doesn't exist yet, it gets created for whichever mint Alice passes
+        let offered_mint = match deposit {
+            Some(_) => *offered_mint_account.key,
+            None => token::unpack_account(temp_token_account)?.mint,
+        };
+        if *offered_mint_account.key != offered_mint {
+            return Err(EscrowError::OfferedMintMismatch.into());
+        }
+        if *requested_mint_account.key != requested_mint {
+            return Err(EscrowError::RequestedMintMismatch.into());
+        }
+        let offered_decimals = match offered_native {
+            true => native::DECIMALS,
+            false => token::unpack_mint(offered_mint_account)?.decimals,
+        };
+        let requested_decimals = match requested_native {
+            true => native::DECIMALS,
+            false => token::unpack_mint(requested_mint_account)?.decimals,
+        };
+
+        let english_auction = match auction {
+            Some(Auction::English(auction)) => Some((
+                auction,
+                next_account_info(account_info_iter)?,
+                next_account_info(account_info_iter)?,
+            )),
+            _ => None,
+        };
+        let initializers_sending_token_account = match deposit {
+            Some(_) => Some(next_account_info(account_info_iter)?),
+            None => None,
+        };
+        let delegate_account = match delegated_amount {
+            Some(_) => Some(next_account_info(account_info_iter)?),
+            None => None,
+        };
+        let system_program = match nonce.is_some() || deposit.is_some() {
+            true => Some(next_account_info(account_info_iter)?),
+            false => None,
+        };
+
+        //an escrow account at a nonce derived address doesn't exist yet, the program creates it with Alice paying the rent
+        if let (Some(nonce), Some(system_program)) = (nonce, system_program) {
+            let (escrow_address, escrow_bump_seed) =
+                Escrow::find_address(initializer.key, nonce, program_id);
+            if *escrow_account.key != escrow_address {
+                return Err(ProgramError::InvalidSeeds);
+            }
+            if escrow_account.data_len() > 0 {
+                return Err(ProgramError::AccountAlreadyInitialized);
+            }
+            msg!("Calling the system program to create the escrow account...");
+            Self::create_pda_account(
+                initializer,
+                escrow_account,
+                system_program,
+                Escrow::LEN,
+                program_id,
+                &[
+                    Escrow::ADDRESS_SEED,
+                    initializer.key.as_ref(),
+                    &nonce.to_le_bytes(),
+                    &[escrow_bump_seed],
+                ],
+            )?;
+        }
 
         //checking if account is rent exempt
         if !rent.is_exempt(escrow_account.lamports(), escrow_account.data_len()) {
@@ -71,52 +334,225 @@ impl Processor {
             return Err(ProgramError::AccountAlreadyInitialized);
         }
 
+        //Program Derived Addresses do not lie on the ed25519 curve and therefore have no private key associated with them.
+        //The PDA is derived from this escrow's own key, so it can only ever sign for this escrow's temp token account
+        let (pda, bump_seed) = Escrow::find_authority_address(escrow_account.key, program_id);
+
         //Time to populate the struct's fields!
         escrow_info.is_initialized = true;
         escrow_info.initializer_pubkey = *initializer.key;
         escrow_info.temp_token_account_pubkey = *temp_token_account.key;
         escrow_info.initializer_token_to_receive_account_pubkey = *token_to_receive_account.key;
         escrow_info.expected_amount = amount;
+        escrow_info.unlock_time = unlock_time;
+        escrow_info.time_out = time_out;
+        escrow_info.bump_seed = bump_seed; //stored so later instructions don't have to search for the bump again
+        escrow_info.offered_mint = offered_mint;
+        escrow_info.requested_mint = requested_mint;
+        escrow_info.taker = taker;
+        escrow_info.offered_decimals = offered_decimals;
+        escrow_info.requested_decimals = requested_decimals;
+        if let Some(Auction::Dutch(auction)) = auction {
+            escrow_info.dutch_auction = Some(auction);
+        }
+
+        //with a deposit the program sets up the vault itself, it belongs to the PDA from the start so there's no authority to hand over
+        if let (Some(deposit), Some(initializers_sending_token_account), Some(system_program)) =
+            (deposit, initializers_sending_token_account, system_program)
+        {
+            let (vault_address, vault_bump_seed) =
+                Escrow::find_vault_address(escrow_account.key, program_id);
+            if *temp_token_account.key != vault_address {
+                return Err(ProgramError::InvalidSeeds);
+            }
+            if temp_token_account.data_len() > 0 {
+                return Err(ProgramError::AccountAlreadyInitialized);
+            }
+            let vault_seeds: &[&[u8]] = &[
+                Escrow::VAULT_SEED,
+                escrow_account.key.as_ref(),
+                &[vault_bump_seed],
+            ];
+
+            if offered_native {
+                //SOL is kept in a vault without data owned by the program, so it can be paid out without any CPI
+                if initializers_sending_token_account.key != initializer.key {
+                    return Err(ProgramError::InvalidAccountData);
+                }
+                msg!("Calling the system program to create the vault account...");
+                Self::create_pda_account(
+                    initializer,
+                    temp_token_account,
+                    system_program,
+                    0,
+                    program_id,
+                    vault_seeds,
+                )?;
+                msg!("Calling the system program to deposit the offered lamports...");
+                native::pay(system_program, initializer, temp_token_account, deposit)?;
+            } else {
+                msg!("Calling the system program to create the vault token account...");
+                Self::create_pda_account(
+                    initializer,
+                    temp_token_account,
+                    system_program,
+                    token::account_len(offered_mint_account)?,
+                    token_program.key,
+                    vault_seeds,
+                )?;
+                msg!("Calling the token program to initialize the vault token account...");
+                token::initialize_account(
+                    token_program,
+                    temp_token_account,
+                    offered_mint_account,
+                    &pda,
+                )?;
+
+                msg!("Calling the token program to deposit the offered tokens...");
+                token::transfer(
+                    token_program,
+                    initializers_sending_token_account,
+                    offered_mint_account,
+                    temp_token_account,
+                    initializer,
+                    token::amount_before_fee(offered_mint_account, deposit)?,
+                    offered_decimals,
+                    &[],
+                )?;
+            }
+        }
+
+        //a delegated escrow leaves the tokens in Alice's account, her delegate PDA is approved for them on top of her other offers
+        if let (Some(delegated_amount), Some(delegate_account)) =
+            (delegated_amount, delegate_account)
+        {
+            let (delegate, _bump_seed) = Escrow::find_delegate_address(initializer.key, program_id);
+            if *delegate_account.key != delegate {
+                return Err(ProgramError::InvalidSeeds);
+            }
+            let token_account_info = token::unpack_account(temp_token_account)?;
+            let approved_amount = match token_account_info.delegate {
+                COption::Some(current_delegate) if current_delegate == delegate => {
+                    token_account_info.delegated_amount
+                }
+                _ => 0,
+            };
+            msg!("Calling the token program to approve the initializer's delegate...");
+            token::approve(
+                token_program,
+                temp_token_account,
+                offered_mint_account,
+                delegate_account,
+                initializer,
+                approved_amount
+                    .checked_add(delegated_amount)
+                    .ok_or(EscrowError::AmountOverflow)?,
+                offered_decimals,
+            )?;
+            escrow_info.delegated_amount = Some(delegated_amount);
+        }
+
+        //the bids of an English auction are escrowed by the same PDA, in a token account Alice hands over just like the temp one
+        if let Some((auction, bid_token_account, requested_token_program)) = english_auction {
+            token::check_token_program(requested_token_program)?;
+            Self::check_mint(
+                bid_token_account,
+                &requested_mint,
+                EscrowError::RequestedMintMismatch,
+            )?;
+            //the account has to start out empty, and the PDA must be able to close it when the auction is settled
+            let bid_token_account_info = token::unpack_account(bid_token_account)?;
+            if bid_token_account_info.amount != 0
+                || bid_token_account_info.close_authority.is_some()
+            {
+                return Err(ProgramError::InvalidAccountData);
+            }
+
+            let owner_change_ix = spl_token_2022::instruction::set_authority(
+                requested_token_program.key,
+                bid_token_account.key,
+                Some(&pda),
+                spl_token_2022::instruction::AuthorityType::AccountOwner,
+                initializer.key,
+                &[initializer.key],
+            )?;
+            msg!("Calling the token program to transfer bid token account ownership...");
+            invoke(
+                &owner_change_ix,
+                &[
+                    bid_token_account.clone(),
+                    initializer.clone(),
+                    requested_token_program.clone(),
+                ],
+            )?;
+
+            escrow_info.english_auction = Some(auction);
+            escrow_info.bid_token_account_pubkey = *bid_token_account.key;
+        }
 
         //pack is another default function which internally calls our pack_into_slice function.
         Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
 
-        //Program Derived Addresses do not lie on the ed25519 curve and therefore have no private key associated with them. */
-        let (pda, _bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+        if deposit.is_none() && delegated_amount.is_none() {
+            //the owner change below doesn't reset a close authority, Alice could otherwise keep the PDA from ever closing the account
+            if token::unpack_account(temp_token_account)?
+                .close_authority
+                .is_some()
+            {
+                return Err(ProgramError::InvalidAccountData);
+            }
 
-        //transfer authority of the temporary token account to the PDA (derived from escrow program)
-        let token_program = next_account_info(account_info_iter)?;
-        let owner_change_ix = spl_token::instruction::set_authority(
-            //token program helper function "set authority"
-            token_program.key,
-            temp_token_account.key,
-            Some(&pda),
-            spl_token::instruction::AuthorityType::AccountOwner,
-            initializer.key,
-            &[&initializer.key],
-        )?;
+            //transfer authority of the temporary token account to the PDA (derived from escrow program)
+            let owner_change_ix = spl_token_2022::instruction::set_authority(
+                //token program helper function "set authority", works for both token programs
+                token_program.key,
+                temp_token_account.key,
+                Some(&pda),
+                spl_token_2022::instruction::AuthorityType::AccountOwner,
+                initializer.key,
+                &[initializer.key],
+            )?;
 
-        //Cross-Program Invocation!
-        msg!("Calling the token program to transfer token account ownership...");
-
-        //program being called through a CPI must be included as an account in the 2nd argument of invoke (and invoke_signed)
-        //check to make sure token program is truly the account of the token program --> spl-token versions above 3.1.1 checks for you!
-        invoke(
-            &owner_change_ix,
-            &[
-                temp_token_account.clone(),
-                initializer.clone(),
-                token_program.clone(),
-            ],
-        )?;
-        //Signature Extension! --> the signature is extended to the CPIs.
+            //Cross-Program Invocation!
+            msg!("Calling the token program to transfer token account ownership...");
+
+            //program being called through a CPI must be included as an account in the 2nd argument of invoke (and invoke_signed)
+            //check to make sure token program is truly the account of the token program --> spl-token versions above 3.1.1 checks for you!
+            invoke(
+                &owner_change_ix,
+                &[
+                    temp_token_account.clone(),
+                    initializer.clone(),
+                    token_program.clone(),
+                ],
+            )?;
+            //Signature Extension! --> the signature is extended to the CPIs.
+        }
+
+        EscrowEvent::Created {
+            escrow: *escrow_account.key,
+            initializer: *initializer.key,
+            offered_mint,
+            requested_mint,
+            offered_amount: match delegated_amount {
+                Some(delegated_amount) => delegated_amount,
+                None if offered_native => native::balance(temp_token_account, program_id)?,
+                None => token::unpack_account(temp_token_account)?.amount,
+            },
+            expected_amount: amount,
+            unlock_time,
+            time_out,
+        }
+        .emit();
 
         Ok(())
     }
 
     fn process_exchange(
         accounts: &[AccountInfo],
-        amount_expected_by_taker: u64,
+        amount_taken: u64,
+        max_requested_amount: Option<u64>,
+        create_receive_account: bool,
         program_id: &Pubkey,
     ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
@@ -133,20 +569,60 @@ impl Processor {
         let takers_token_to_receive_account = next_account_info(account_info_iter)?;
 
         let pdas_temp_token_account = next_account_info(account_info_iter)?;
-        let pdas_temp_token_account_info =
-            TokenAccount::unpack(&pdas_temp_token_account.try_borrow_data()?)?;
-        let (pda, bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
-
-        //check the amount in the PDA's X token account is what Bob expects
-        if amount_expected_by_taker != pdas_temp_token_account_info.amount {
-            return Err(EscrowError::ExpectedAmountMismatch.into());
-        }
 
         let initializers_main_account = next_account_info(account_info_iter)?;
         let initializers_token_to_receive_account = next_account_info(account_info_iter)?;
         let escrow_account = next_account_info(account_info_iter)?;
 
-        let escrow_info = Escrow::unpack(&escrow_account.try_borrow_data()?)?;
+        //a delegated escrow signs for the initializer it names, a forged account could otherwise name anyone with an approval
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut escrow_info = Escrow::unpack(&escrow_account.try_borrow_data()?)?;
+
+        //an English auction only sells to its highest bidder
+        if escrow_info.english_auction.is_some() {
+            return Err(EscrowError::InvalidEscrowMode.into());
+        }
+
+        //either leg may be native SOL instead of a token
+        let offered_native = native::is_native(&escrow_info.offered_mint);
+        let requested_native = native::is_native(&escrow_info.requested_mint);
+
+        let offered_amount = if offered_native {
+            native::balance(pdas_temp_token_account, program_id)?
+        } else {
+            let pdas_temp_token_account_info = token::unpack_account(pdas_temp_token_account)?;
+            if pdas_temp_token_account_info.mint != escrow_info.offered_mint {
+                return Err(EscrowError::OfferedMintMismatch.into());
+            }
+            //a delegated escrow offers what's left of its own amount, Alice's token account may back other offers as well
+            escrow_info
+                .delegated_amount
+                .unwrap_or(pdas_temp_token_account_info.amount)
+        };
+
+        //Bob may take all of the offered X tokens, or only a part of them
+        if amount_taken > offered_amount {
+            return Err(EscrowError::ExpectedAmountMismatch.into());
+        }
+        if amount_taken == 0 {
+            return Err(EscrowError::InvalidFillAmount.into());
+        }
+
+        //a private trade was negotiated with one counterparty, nobody else may snipe it
+        if matches!(escrow_info.taker, Some(designated) if designated != *taker.key) {
+            return Err(EscrowError::TakerMismatch.into());
+        }
+
+        //the trade can only be taken between unlock_time and time_out, Clock::get reads the sysvar without needing an extra account
+        let now = Clock::get()?.unix_timestamp as u64;
+        if now < escrow_info.unlock_time {
+            return Err(EscrowError::EscrowTimeUnlock.into());
+        }
+        if now > escrow_info.time_out {
+            return Err(EscrowError::EscrowTimeOverflow.into());
+        }
 
         if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key {
             return Err(ProgramError::InvalidAccountData);
@@ -162,77 +638,1319 @@ impl Processor {
             return Err(ProgramError::InvalidAccountData);
         }
 
-        let token_program = next_account_info(account_info_iter)?;
+        //all four token accounts have to be of the mints Alice set up the trade with (the temp one was checked above)
+        if offered_native {
+            //lamports can be paid into any account, there's no associated token account to create for them
+            if create_receive_account {
+                return Err(EscrowError::InvalidInstruction.into());
+            }
+        } else if !create_receive_account {
+            //Bob's receiving account may only be created further down
+            Self::check_mint(
+                takers_token_to_receive_account,
+                &escrow_info.offered_mint,
+                EscrowError::OfferedMintMismatch,
+            )?;
+        }
+        if requested_native {
+            //Bob pays the SOL out of his own wallet, the receiving side is Alice's wallet as checked above
+            if takers_sending_token_account.key != taker.key {
+                return Err(ProgramError::InvalidAccountData);
+            }
+        } else {
+            Self::check_mint(
+                takers_sending_token_account,
+                &escrow_info.requested_mint,
+                EscrowError::RequestedMintMismatch,
+            )?;
+            Self::check_mint(
+                initializers_token_to_receive_account,
+                &escrow_info.requested_mint,
+                EscrowError::RequestedMintMismatch,
+            )?;
+        }
+
+        //the tokens of a delegated escrow are pulled by Alice's delegate PDA instead of the escrow's own one
+        let (authority_seed, authority_key, pda, bump_seed) = match escrow_info.delegated_amount {
+            Some(_) => {
+                let (delegate, bump_seed) =
+                    Escrow::find_delegate_address(&escrow_info.initializer_pubkey, program_id);
+                (
+                    Escrow::DELEGATE_SEED,
+                    escrow_info.initializer_pubkey,
+                    delegate,
+                    bump_seed,
+                )
+            }
+            None => (
+                Escrow::AUTHORITY_SEED,
+                *escrow_account.key,
+                Self::authority_address(escrow_account.key, escrow_info.bump_seed, program_id)?,
+                escrow_info.bump_seed,
+            ),
+        };
+        let authority_seeds: &[&[u8]] = &[authority_seed, authority_key.as_ref(), &[bump_seed]];
+
+        //Bob pays his share of the price for the part he takes, an auction's price depends on when he takes it
+        let current_amount = match escrow_info.dutch_auction {
+            Some(auction) => auction.current_amount(escrow_info.expected_amount, now)?,
+            None => escrow_info.expected_amount,
+        };
+        let amount_owed = Self::pro_rata_amount(current_amount, amount_taken, offered_amount)?;
+        //Alice may have repriced the trade after Bob looked at it
+        if matches!(max_requested_amount, Some(limit) if amount_owed > limit) {
+            return Err(EscrowError::PriceLimitExceeded.into());
+        }
+        let remaining_amount = offered_amount - amount_taken;
+        let remaining_expected_amount = match escrow_info.dutch_auction.as_mut() {
+            //what's left of an auction keeps falling on the same schedule, from a start and floor scaled down to its size
+            Some(auction) => {
+                auction.start_amount =
+                    Self::pro_rata_amount(auction.start_amount, remaining_amount, offered_amount)?;
+                Self::pro_rata_amount(
+                    escrow_info.expected_amount,
+                    remaining_amount,
+                    offered_amount,
+                )?
+            }
+            None => escrow_info.expected_amount - amount_owed,
+        };
+
+        //whatever is left open must still cost something, otherwise the next taker would get it for free
+        if remaining_amount > 0 && remaining_expected_amount == 0 {
+            return Err(EscrowError::InvalidFillAmount.into());
+        }
 
-        //transfer tokens!
-        let transfer_to_initializer_ix = spl_token::instruction::transfer(
-            token_program.key,
-            takers_sending_token_account.key,
-            initializers_token_to_receive_account.key,
-            taker.key,
-            &[&taker.key],
-            escrow_info.expected_amount,
+        let offered_token_program = next_account_info(account_info_iter)?;
+        let pda_account = next_account_info(account_info_iter)?;
+        if *pda_account.key != pda {
+            return Err(ProgramError::InvalidSeeds);
+        }
+
+        let offered_fee_account = next_account_info(account_info_iter)?;
+        let requested_fee_account = next_account_info(account_info_iter)?;
+        let config = Self::load_config(next_account_info(account_info_iter)?, program_id)?;
+
+        if remaining_amount > 0 && !config.is_enabled(Config::FEATURE_PARTIAL_FILLS) {
+            return Err(EscrowError::FeatureDisabled.into());
+        }
+
+        //the two legs may live in different token programs (e.g. a Token-2022 token traded for USDC)
+        let requested_token_program = next_account_info(account_info_iter)?;
+        let offered_mint = next_account_info(account_info_iter)?;
+        if *offered_mint.key != escrow_info.offered_mint {
+            return Err(EscrowError::OfferedMintMismatch.into());
+        }
+        let requested_mint = next_account_info(account_info_iter)?;
+        if *requested_mint.key != escrow_info.requested_mint {
+            return Err(EscrowError::RequestedMintMismatch.into());
+        }
+        native::check_leg_program(offered_token_program, offered_mint)?;
+        native::check_leg_program(requested_token_program, requested_mint)?;
+
+        //a first time buyer of the offered token gets an associated token account created on the spot, paying its rent
+        if create_receive_account {
+            let system_program = next_account_info(account_info_iter)?;
+            let associated_token_program = next_account_info(account_info_iter)?;
+            msg!("Calling the associated token account program to create the taker's token account...");
+            token::create_associated_account(
+                associated_token_program,
+                offered_token_program,
+                system_program,
+                takers_token_to_receive_account,
+                offered_mint,
+                taker,
+            )?;
+        }
+
+        //the protocol skims its fee off both legs before they reach Alice and Bob
+        let offered_fee = config.fee.offered_fee(amount_taken)?;
+        let requested_fee = config.fee.requested_fee(amount_owed)?;
+        Self::check_fee_account(
+            offered_fee_account,
+            offered_fee,
+            &escrow_info.offered_mint,
+            &config.fee_owner,
+        )?;
+        Self::check_fee_account(
+            requested_fee_account,
+            requested_fee,
+            &escrow_info.requested_mint,
+            &config.fee_owner,
         )?;
-        msg!("Calling the token program to transfer tokens to the escrow's initializer...");
-        invoke(
-            &transfer_to_initializer_ix,
-            &[
-                takers_sending_token_account.clone(),
-                initializers_token_to_receive_account.clone(),
-                taker.clone(),
-                token_program.clone(),
-            ],
+
+        if requested_native {
+            msg!("Calling the system program to transfer SOL to the escrow's initializer...");
+            native::pay(
+                requested_token_program,
+                taker,
+                initializers_token_to_receive_account,
+                amount_owed - requested_fee,
+            )?;
+            if requested_fee > 0 {
+                msg!(
+                    "Calling the system program to transfer the protocol fee on the taker's SOL..."
+                );
+                native::pay(
+                    requested_token_program,
+                    taker,
+                    requested_fee_account,
+                    requested_fee,
+                )?;
+            }
+        } else {
+            //transfer tokens! Bob also covers any transfer fee of the requested mint, so Alice and the protocol get their full share
+            msg!("Calling the token program to transfer tokens to the escrow's initializer...");
+            token::transfer(
+                requested_token_program,
+                takers_sending_token_account,
+                requested_mint,
+                initializers_token_to_receive_account,
+                taker,
+                token::amount_before_fee(requested_mint, amount_owed - requested_fee)?,
+                escrow_info.requested_decimals,
+                &[],
+            )?;
+
+            if requested_fee > 0 {
+                msg!("Calling the token program to transfer the protocol fee on the taker's tokens...");
+                token::transfer(
+                    requested_token_program,
+                    takers_sending_token_account,
+                    requested_mint,
+                    requested_fee_account,
+                    taker,
+                    token::amount_before_fee(requested_mint, requested_fee)?,
+                    escrow_info.requested_decimals,
+                    &[],
+                )?;
+            }
+        }
+
+        if offered_native {
+            //the vault is ours, so its SOL is paid out without a CPI
+            native::withdraw(
+                pdas_temp_token_account,
+                takers_token_to_receive_account,
+                amount_taken - offered_fee,
+            )?;
+            if offered_fee > 0 {
+                native::withdraw(pdas_temp_token_account, offered_fee_account, offered_fee)?;
+            }
+        } else {
+            //the offered tokens can't be grossed up, the escrow only holds what Alice deposited
+            msg!("Calling the token program to transfer tokens to the taker...");
+            token::transfer(
+                offered_token_program,
+                pdas_temp_token_account,
+                offered_mint,
+                takers_token_to_receive_account,
+                pda_account,
+                amount_taken - offered_fee,
+                escrow_info.offered_decimals,
+                &[authority_seeds],
+            )?;
+
+            if offered_fee > 0 {
+                msg!("Calling the token program to transfer the protocol fee on the initializer's tokens...");
+                token::transfer(
+                    offered_token_program,
+                    pdas_temp_token_account,
+                    offered_mint,
+                    offered_fee_account,
+                    pda_account,
+                    offered_fee,
+                    escrow_info.offered_decimals,
+                    &[authority_seeds],
+                )?;
+            }
+        }
+
+        EscrowEvent::Exchanged {
+            escrow: *escrow_account.key,
+            initializer: escrow_info.initializer_pubkey,
+            taker: *taker.key,
+            offered_mint: escrow_info.offered_mint,
+            requested_mint: escrow_info.requested_mint,
+            offered_amount: amount_taken,
+            requested_amount: amount_owed,
+            remaining_amount,
+            offered_fee,
+            requested_fee,
+        }
+        .emit();
+
+        //partial fill: the rest of the trade stays open at the remaining price
+        if remaining_amount > 0 {
+            escrow_info.expected_amount = remaining_expected_amount;
+            if escrow_info.delegated_amount.is_some() {
+                escrow_info.delegated_amount = Some(remaining_amount);
+            }
+            Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+            return Ok(());
+        }
+
+        //Alice's own token account stays open after a delegated escrow is taken
+        if offered_native {
+            msg!("Closing the escrow's SOL vault...");
+            Self::close_program_account(pdas_temp_token_account, initializers_main_account)?;
+        } else if escrow_info.delegated_amount.is_none() {
+            msg!("Calling the token program to close pda's temp account...");
+            token::close_account(
+                offered_token_program,
+                pdas_temp_token_account,
+                offered_mint,
+                initializers_main_account,
+                pda_account,
+                &[authority_seeds],
+            )?;
+        }
+
+        msg!("Closing the escrow account...");
+        Self::close_program_account(escrow_account, initializers_main_account)?;
+
+        Ok(())
+    }
+
+    fn process_cancel(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let initializer = next_account_info(account_info_iter)?;
+
+        //only Alice may take her tokens back, otherwise anyone could cancel someone else's trade
+        if !initializer.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let pdas_temp_token_account = next_account_info(account_info_iter)?;
+
+        let initializers_token_to_refund_account = next_account_info(account_info_iter)?;
+        let escrow_account = next_account_info(account_info_iter)?;
+
+        //only an escrow of this program gets to decide what's refunded
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let escrow_info = Escrow::unpack(&escrow_account.try_borrow_data()?)?;
+
+        if escrow_info.initializer_pubkey != *initializer.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        //bidders rely on an English auction running until its end
+        if escrow_info.english_auction.is_some() {
+            return Err(EscrowError::InvalidEscrowMode.into());
+        }
+
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let pda = match escrow_info.delegated_amount {
+            Some(_) => Escrow::find_delegate_address(initializer.key, program_id).0,
+            None => Self::authority_address(escrow_account.key, escrow_info.bump_seed, program_id)?,
+        };
+        let authority_seeds: &[&[u8]] = &[
+            Escrow::AUTHORITY_SEED,
+            escrow_account.key.as_ref(),
+            &[escrow_info.bump_seed],
+        ];
+
+        let token_program = next_account_info(account_info_iter)?;
+        let pda_account = next_account_info(account_info_iter)?;
+        if *pda_account.key != pda {
+            return Err(ProgramError::InvalidSeeds);
+        }
+
+        let offered_mint = next_account_info(account_info_iter)?;
+        if *offered_mint.key != escrow_info.offered_mint {
+            return Err(EscrowError::OfferedMintMismatch.into());
+        }
+        native::check_leg_program(token_program, offered_mint)?;
+
+        let refunded_amount = if native::is_native(&escrow_info.offered_mint) {
+            //the SOL goes back to Alice's wallet together with the vault's rent
+            msg!("Closing the escrow's SOL vault...");
+            let refunded_amount = native::balance(pdas_temp_token_account, program_id)?;
+            Self::close_program_account(pdas_temp_token_account, initializer)?;
+            refunded_amount
+        } else {
+            let pdas_temp_token_account_info = token::unpack_account(pdas_temp_token_account)?;
+            match escrow_info.delegated_amount {
+                //the tokens never left Alice's account, only her delegate's approval for them is taken back
+                Some(delegated_amount) => {
+                    if pdas_temp_token_account_info.delegate == COption::Some(pda) {
+                        let approved_amount = pdas_temp_token_account_info
+                            .delegated_amount
+                            .saturating_sub(delegated_amount);
+                        msg!("Calling the token program to reduce the delegate's approval...");
+                        match approved_amount {
+                            0 => {
+                                token::revoke(token_program, pdas_temp_token_account, initializer)?
+                            }
+                            _ => token::approve(
+                                token_program,
+                                pdas_temp_token_account,
+                                offered_mint,
+                                pda_account,
+                                initializer,
+                                approved_amount,
+                                escrow_info.offered_decimals,
+                            )?,
+                        }
+                    }
+                    delegated_amount
+                }
+                None => {
+                    //send the offered tokens back to Alice (the token program checks the refund account has the same mint)
+                    msg!(
+                        "Calling the token program to refund tokens to the escrow's initializer..."
+                    );
+                    token::transfer(
+                        token_program,
+                        pdas_temp_token_account,
+                        offered_mint,
+                        initializers_token_to_refund_account,
+                        pda_account,
+                        pdas_temp_token_account_info.amount,
+                        escrow_info.offered_decimals,
+                        &[authority_seeds],
+                    )?;
+
+                    msg!("Calling the token program to close pda's temp account...");
+                    token::close_account(
+                        token_program,
+                        pdas_temp_token_account,
+                        offered_mint,
+                        initializer,
+                        pda_account,
+                        &[authority_seeds],
+                    )?;
+                    pdas_temp_token_account_info.amount
+                }
+            }
+        };
+
+        msg!("Closing the escrow account...");
+        Self::close_program_account(escrow_account, initializer)?;
+
+        EscrowEvent::Cancelled {
+            escrow: *escrow_account.key,
+            initializer: *initializer.key,
+            offered_mint: escrow_info.offered_mint,
+            refunded_amount,
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    fn process_expire(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let caller = next_account_info(account_info_iter)?;
+
+        //anyone may expire an escrow, but the signature makes sure the bounty goes to whoever sent the transaction
+        if !caller.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let pdas_temp_token_account = next_account_info(account_info_iter)?;
+        let initializers_token_to_refund_account = next_account_info(account_info_iter)?;
+        let initializers_main_account = next_account_info(account_info_iter)?;
+        let escrow_account = next_account_info(account_info_iter)?;
+
+        //without Alice's signature nothing vouches for this account, so it has to be one of ours
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let escrow_info = Escrow::unpack(&escrow_account.try_borrow_data()?)?;
+
+        //a timed out English auction may have a winner, SettleAuction takes care of it
+        if escrow_info.english_auction.is_some() {
+            return Err(EscrowError::InvalidEscrowMode.into());
+        }
+
+        let now = Clock::get()?.unix_timestamp as u64;
+        if now <= escrow_info.time_out {
+            return Err(EscrowError::EscrowNotExpired.into());
+        }
+
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if escrow_info.initializer_pubkey != *initializers_main_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        //the tokens may only go back to Alice, the token program checks the mint once more. SOL goes to her wallet
+        let offered_native = native::is_native(&escrow_info.offered_mint);
+        if !offered_native {
+            let refund_account_info = token::unpack_account(initializers_token_to_refund_account)?;
+            if refund_account_info.owner != escrow_info.initializer_pubkey {
+                return Err(ProgramError::InvalidAccountData);
+            }
+        }
+
+        let pda = match escrow_info.delegated_amount {
+            Some(_) => Escrow::find_delegate_address(&escrow_info.initializer_pubkey, program_id).0,
+            None => Self::authority_address(escrow_account.key, escrow_info.bump_seed, program_id)?,
+        };
+        let authority_seeds: &[&[u8]] = &[
+            Escrow::AUTHORITY_SEED,
+            escrow_account.key.as_ref(),
+            &[escrow_info.bump_seed],
+        ];
+
+        let token_program = next_account_info(account_info_iter)?;
+        let pda_account = next_account_info(account_info_iter)?;
+        if *pda_account.key != pda {
+            return Err(ProgramError::InvalidSeeds);
+        }
+
+        let offered_mint = next_account_info(account_info_iter)?;
+        if *offered_mint.key != escrow_info.offered_mint {
+            return Err(EscrowError::OfferedMintMismatch.into());
+        }
+        native::check_leg_program(token_program, offered_mint)?;
+
+        let config = Self::load_config(next_account_info(account_info_iter)?, program_id)?;
+
+        //the tokens of a delegated escrow never left Alice's account, so nothing is refunded. Only she can take back the
+        //approval, until she does it's a leftover the program never uses since no escrow is left to account for it
+        let refunded_amount = match escrow_info.delegated_amount {
+            _ if offered_native => {
+                msg!("Closing the escrow's SOL vault...");
+                let refunded_amount = native::balance(pdas_temp_token_account, program_id)?;
+                Self::close_program_account(pdas_temp_token_account, initializers_main_account)?;
+                refunded_amount
+            }
+            Some(_) => 0,
+            None => {
+                let pdas_temp_token_account_info = token::unpack_account(pdas_temp_token_account)?;
+                msg!("Calling the token program to refund tokens to the escrow's initializer...");
+                token::transfer(
+                    token_program,
+                    pdas_temp_token_account,
+                    offered_mint,
+                    initializers_token_to_refund_account,
+                    pda_account,
+                    pdas_temp_token_account_info.amount,
+                    escrow_info.offered_decimals,
+                    &[authority_seeds],
+                )?;
+
+                msg!("Calling the token program to close pda's temp account...");
+                token::close_account(
+                    token_program,
+                    pdas_temp_token_account,
+                    offered_mint,
+                    initializers_main_account,
+                    pda_account,
+                    &[authority_seeds],
+                )?;
+                pdas_temp_token_account_info.amount
+            }
+        };
+
+        //the caller's bounty comes out of the escrow account's rent, Alice gets the rest
+        msg!("Closing the escrow account...");
+        let bounty = config.expire_bounty.min(escrow_account.lamports());
+        native::withdraw(escrow_account, caller, bounty)?;
+        Self::close_program_account(escrow_account, initializers_main_account)?;
+
+        EscrowEvent::Expired {
+            escrow: *escrow_account.key,
+            initializer: escrow_info.initializer_pubkey,
+            offered_mint: escrow_info.offered_mint,
+            refunded_amount,
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    fn process_update_expected_amount(
+        accounts: &[AccountInfo],
+        amount: u64,
+        deadlines: Option<(u64, u64)>,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let initializer = next_account_info(account_info_iter)?;
+
+        //only Alice may reprice her own trade
+        if !initializer.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let escrow_account = next_account_info(account_info_iter)?;
+        //a forged account could pass for any escrow, only ours can be repriced
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut escrow_info = Escrow::unpack(&escrow_account.try_borrow_data()?)?;
+        if escrow_info.initializer_pubkey != *initializer.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        //bidders rely on an English auction's reserve price and time window
+        if escrow_info.english_auction.is_some() {
+            return Err(EscrowError::InvalidEscrowMode.into());
+        }
+
+        //the trade must still cost something, otherwise the next taker would get it for free
+        if amount == 0 {
+            return Err(EscrowError::InvalidInstruction.into());
+        }
+        //an auction's price can only fall to its floor
+        if matches!(escrow_info.dutch_auction, Some(auction) if !auction.is_valid(amount)) {
+            return Err(EscrowError::InvalidInstruction.into());
+        }
+        if let Some((unlock_time, time_out)) = deadlines {
+            //same rule as in InitEscrow, a trade that times out before it unlocks could never be taken
+            if unlock_time >= time_out {
+                return Err(EscrowError::InvalidInstruction.into());
+            }
+            escrow_info.unlock_time = unlock_time;
+            escrow_info.time_out = time_out;
+        }
+        escrow_info.expected_amount = amount;
+
+        EscrowEvent::Updated {
+            escrow: *escrow_account.key,
+            initializer: *initializer.key,
+            expected_amount: amount,
+            unlock_time: escrow_info.unlock_time,
+            time_out: escrow_info.time_out,
+        }
+        .emit();
+
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_place_bid(
+        accounts: &[AccountInfo],
+        amount: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let bidder = next_account_info(account_info_iter)?;
+
+        if !bidder.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let bidders_sending_token_account = next_account_info(account_info_iter)?;
+        let escrow_account = next_account_info(account_info_iter)?;
+        //the bids go wherever the escrow says, so it has to be one of ours
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut escrow_info = Escrow::unpack(&escrow_account.try_borrow_data()?)?;
+        let auction = escrow_info
+            .english_auction
+            .ok_or(EscrowError::InvalidEscrowMode)?;
+
+        //bids are only taken while the trade could be taken
+        let now = Clock::get()?.unix_timestamp as u64;
+        if now < escrow_info.unlock_time {
+            return Err(EscrowError::EscrowTimeUnlock.into());
+        }
+        if now > escrow_info.time_out {
+            return Err(EscrowError::EscrowTimeOverflow.into());
+        }
+
+        if amount
+            < auction.min_bid(
+                escrow_info.expected_amount,
+                escrow_info.highest_bid.as_ref(),
+            )?
+        {
+            return Err(EscrowError::BidTooLow.into());
+        }
+
+        let bid_token_account = next_account_info(account_info_iter)?;
+        if escrow_info.bid_token_account_pubkey != *bid_token_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let previous_bidders_refund_account = next_account_info(account_info_iter)?;
+
+        let requested_token_program = next_account_info(account_info_iter)?;
+        token::check_token_program(requested_token_program)?;
+        let requested_mint = next_account_info(account_info_iter)?;
+        if *requested_mint.key != escrow_info.requested_mint {
+            return Err(EscrowError::RequestedMintMismatch.into());
+        }
+        if requested_mint.owner != requested_token_program.key {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let pda = Self::authority_address(escrow_account.key, escrow_info.bump_seed, program_id)?;
+        let pda_account = next_account_info(account_info_iter)?;
+        if *pda_account.key != pda {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        let authority_seeds: &[&[u8]] = &[
+            Escrow::AUTHORITY_SEED,
+            escrow_account.key.as_ref(),
+            &[escrow_info.bump_seed],
+        ];
+
+        //like a taker, the bidder covers any transfer fee so the whole bid ends up in the bid token account
+        msg!("Calling the token program to transfer the bid...");
+        token::transfer(
+            requested_token_program,
+            bidders_sending_token_account,
+            requested_mint,
+            bid_token_account,
+            bidder,
+            token::amount_before_fee(requested_mint, amount)?,
+            escrow_info.requested_decimals,
+            &[],
         )?;
 
+        //any account of the previous bidder will do, so closing the one they bid from can't keep them from being outbid
+        if let Some(previous_bid) = escrow_info.highest_bid {
+            let refund_account_info = token::unpack_account(previous_bidders_refund_account)?;
+            if refund_account_info.owner != previous_bid.bidder {
+                return Err(ProgramError::InvalidAccountData);
+            }
+            msg!("Calling the token program to refund the previous bid...");
+            token::transfer(
+                requested_token_program,
+                bid_token_account,
+                requested_mint,
+                previous_bidders_refund_account,
+                pda_account,
+                previous_bid.amount,
+                escrow_info.requested_decimals,
+                &[authority_seeds],
+            )?;
+        }
+
+        escrow_info.highest_bid = Some(Bid {
+            bidder: *bidder.key,
+            amount,
+        });
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        EscrowEvent::BidPlaced {
+            escrow: *escrow_account.key,
+            bidder: *bidder.key,
+            amount,
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    fn process_settle_auction(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let caller = next_account_info(account_info_iter)?;
+
+        if !caller.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let pdas_temp_token_account = next_account_info(account_info_iter)?;
+        let pdas_temp_token_account_info = token::unpack_account(pdas_temp_token_account)?;
+        let bid_token_account = next_account_info(account_info_iter)?;
+        let bid_token_account_info = token::unpack_account(bid_token_account)?;
+        let recipients_token_account = next_account_info(account_info_iter)?;
+        let initializers_token_to_receive_account = next_account_info(account_info_iter)?;
+        let initializers_main_account = next_account_info(account_info_iter)?;
+        let escrow_account = next_account_info(account_info_iter)?;
+
+        //nobody's signature vouches for this account, so it has to be one of ours
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let escrow_info = Escrow::unpack(&escrow_account.try_borrow_data()?)?;
+        if escrow_info.english_auction.is_none() {
+            return Err(EscrowError::InvalidEscrowMode.into());
+        }
+
+        let now = Clock::get()?.unix_timestamp as u64;
+        if now <= escrow_info.time_out {
+            return Err(EscrowError::EscrowNotExpired.into());
+        }
+
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key
+            || escrow_info.bid_token_account_pubkey != *bid_token_account.key
+            || escrow_info.initializer_pubkey != *initializers_main_account.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        //the offered tokens go to the winner, or back to Alice if nobody bid
+        let recipient = escrow_info
+            .highest_bid
+            .map_or(escrow_info.initializer_pubkey, |bid| bid.bidder);
+        if token::unpack_account(recipients_token_account)?.owner != recipient {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if token::unpack_account(initializers_token_to_receive_account)?.owner
+            != escrow_info.initializer_pubkey
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let offered_token_program = next_account_info(account_info_iter)?;
+        let requested_token_program = next_account_info(account_info_iter)?;
+        token::check_token_program(offered_token_program)?;
+        token::check_token_program(requested_token_program)?;
+
+        let pda = Self::authority_address(escrow_account.key, escrow_info.bump_seed, program_id)?;
         let pda_account = next_account_info(account_info_iter)?;
+        if *pda_account.key != pda {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        let authority_seeds: &[&[u8]] = &[
+            Escrow::AUTHORITY_SEED,
+            escrow_account.key.as_ref(),
+            &[escrow_info.bump_seed],
+        ];
 
-        let transfer_to_taker_ix = spl_token::instruction::transfer(
-            token_program.key,
-            pdas_temp_token_account.key,
-            takers_token_to_receive_account.key,
-            &pda,
-            &[&pda],
-            pdas_temp_token_account_info.amount,
+        let offered_mint = next_account_info(account_info_iter)?;
+        if *offered_mint.key != escrow_info.offered_mint {
+            return Err(EscrowError::OfferedMintMismatch.into());
+        }
+        let requested_mint = next_account_info(account_info_iter)?;
+        if *requested_mint.key != escrow_info.requested_mint {
+            return Err(EscrowError::RequestedMintMismatch.into());
+        }
+
+        let offered_fee_account = next_account_info(account_info_iter)?;
+        let requested_fee_account = next_account_info(account_info_iter)?;
+        let config = Self::load_config(next_account_info(account_info_iter)?, program_id)?;
+
+        //only a sale pays the protocol fee
+        let (offered_fee, requested_fee) = match escrow_info.highest_bid {
+            Some(bid) => (
+                config
+                    .fee
+                    .offered_fee(pdas_temp_token_account_info.amount)?,
+                config.fee.requested_fee(bid.amount)?,
+            ),
+            None => (0, 0),
+        };
+        Self::check_fee_account(
+            offered_fee_account,
+            offered_fee,
+            &escrow_info.offered_mint,
+            &config.fee_owner,
         )?;
-        msg!("Calling the token program to transfer tokens to the taker...");
-        invoke_signed(
-            &transfer_to_taker_ix,
-            &[
-                pdas_temp_token_account.clone(),
-                takers_token_to_receive_account.clone(),
-                pda_account.clone(),
-                token_program.clone(),
-            ],
-            &[&[&b"escrow"[..], &[bump_seed]]],
+        Self::check_fee_account(
+            requested_fee_account,
+            requested_fee,
+            &escrow_info.requested_mint,
+            &config.fee_owner,
         )?;
 
-        let close_pdas_temp_acc_ix = spl_token::instruction::close_account(
-            token_program.key,
-            pdas_temp_token_account.key,
-            initializers_main_account.key,
-            &pda,
-            &[&pda],
+        msg!("Calling the token program to transfer the offered tokens...");
+        token::transfer(
+            offered_token_program,
+            pdas_temp_token_account,
+            offered_mint,
+            recipients_token_account,
+            pda_account,
+            pdas_temp_token_account_info.amount - offered_fee,
+            escrow_info.offered_decimals,
+            &[authority_seeds],
         )?;
+        if offered_fee > 0 {
+            msg!("Calling the token program to transfer the protocol fee on the initializer's tokens...");
+            token::transfer(
+                offered_token_program,
+                pdas_temp_token_account,
+                offered_mint,
+                offered_fee_account,
+                pda_account,
+                offered_fee,
+                escrow_info.offered_decimals,
+                &[authority_seeds],
+            )?;
+        }
+
+        //everything in the bid token account goes to Alice, so tokens sent to it by anyone else can't keep it from closing
+        if bid_token_account_info.amount > 0 {
+            msg!("Calling the token program to transfer the bid to the escrow's initializer...");
+            token::transfer(
+                requested_token_program,
+                bid_token_account,
+                requested_mint,
+                initializers_token_to_receive_account,
+                pda_account,
+                bid_token_account_info.amount - requested_fee,
+                escrow_info.requested_decimals,
+                &[authority_seeds],
+            )?;
+        }
+        if requested_fee > 0 {
+            msg!("Calling the token program to transfer the protocol fee on the bid...");
+            token::transfer(
+                requested_token_program,
+                bid_token_account,
+                requested_mint,
+                requested_fee_account,
+                pda_account,
+                requested_fee,
+                escrow_info.requested_decimals,
+                &[authority_seeds],
+            )?;
+        }
+
         msg!("Calling the token program to close pda's temp account...");
-        invoke_signed(
-            &close_pdas_temp_acc_ix,
-            &[
-                pdas_temp_token_account.clone(),
-                initializers_main_account.clone(),
-                pda_account.clone(),
-                token_program.clone(),
-            ],
-            &[&[&b"escrow"[..], &[bump_seed]]],
+        token::close_account(
+            offered_token_program,
+            pdas_temp_token_account,
+            offered_mint,
+            initializers_main_account,
+            pda_account,
+            &[authority_seeds],
+        )?;
+        msg!("Calling the token program to close pda's bid token account...");
+        token::close_account(
+            requested_token_program,
+            bid_token_account,
+            requested_mint,
+            initializers_main_account,
+            pda_account,
+            &[authority_seeds],
         )?;
 
         msg!("Closing the escrow account...");
-        **initializers_main_account.lamports.borrow_mut() = initializers_main_account
-            .lamports()
-            .checked_add(escrow_account.lamports())
-            .ok_or(EscrowError::AmountOverflow)?;
-        **escrow_account.lamports.borrow_mut() = 0;
-        *escrow_account.try_borrow_mut_data()? = &mut [];
+        Self::close_program_account(escrow_account, initializers_main_account)?;
+
+        match escrow_info.highest_bid {
+            Some(bid) => EscrowEvent::Exchanged {
+                escrow: *escrow_account.key,
+                initializer: escrow_info.initializer_pubkey,
+                taker: bid.bidder,
+                offered_mint: escrow_info.offered_mint,
+                requested_mint: escrow_info.requested_mint,
+                offered_amount: pdas_temp_token_account_info.amount,
+                requested_amount: bid.amount,
+                remaining_amount: 0,
+                offered_fee,
+                requested_fee,
+            },
+            None => EscrowEvent::Expired {
+                escrow: *escrow_account.key,
+                initializer: escrow_info.initializer_pubkey,
+                offered_mint: escrow_info.offered_mint,
+                refunded_amount: pdas_temp_token_account_info.amount,
+            },
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    fn process_migrate_escrow(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let payer = next_account_info(account_info_iter)?;
+
+        if !payer.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let escrow_account = next_account_info(account_info_iter)?;
+        //we are about to resize and rewrite this account, so it really has to be one of ours
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let version = *escrow_account
+            .try_borrow_data()?
+            .first()
+            .ok_or(ProgramError::InvalidAccountData)?;
+        if version == Escrow::VERSION {
+            msg!("Escrow account already uses the current layout");
+            return Ok(());
+        }
+
+        //Pack::unpack insists on the current length, the old layouts are decoded by calling unpack_from_slice directly
+        let mut escrow_info = Escrow::unpack_from_slice(&escrow_account.try_borrow_data()?)?;
+        if !escrow_info.is_initialized() {
+            return Err(ProgramError::UninitializedAccount);
+        }
+
+        let temp_token_account = next_account_info(account_info_iter)?;
+        if escrow_info.temp_token_account_pubkey != *temp_token_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let token_to_receive_account = next_account_info(account_info_iter)?;
+        if escrow_info.initializer_token_to_receive_account_pubkey != *token_to_receive_account.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let token_program = next_account_info(account_info_iter)?;
+        let legacy_pda_account = next_account_info(account_info_iter)?;
+        let system_program = next_account_info(account_info_iter)?;
+        let offered_mint_account = next_account_info(account_info_iter)?;
+        let requested_mint_account = next_account_info(account_info_iter)?;
+
+        //version 1 -> 2: the mints weren't stored yet and one program-wide PDA owned every temp token account
+        if version == 1 {
+            escrow_info.offered_mint = token::unpack_account(temp_token_account)?.mint;
+            escrow_info.requested_mint = token::unpack_account(token_to_receive_account)?.mint;
+
+            //the legacy PDA still owns the temp token account of every unmigrated escrow, its signature may only ever reach
+            //the original token program (version 1 predates Token-2022)
+            if *token_program.key != spl_token::id() {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+
+            let (legacy_pda, legacy_bump_seed) =
+                Pubkey::find_program_address(&[Escrow::AUTHORITY_SEED], program_id);
+            if *legacy_pda_account.key != legacy_pda {
+                return Err(ProgramError::InvalidSeeds);
+            }
+
+            let (pda, bump_seed) = Escrow::find_authority_address(escrow_account.key, program_id);
+            escrow_info.bump_seed = bump_seed;
+
+            let owner_change_ix = spl_token::instruction::set_authority(
+                token_program.key,
+                temp_token_account.key,
+                Some(&pda),
+                spl_token::instruction::AuthorityType::AccountOwner,
+                &legacy_pda,
+                &[&legacy_pda],
+            )?;
+            msg!("Calling the token program to hand the temp token account to the escrow's PDA...");
+            invoke_signed(
+                &owner_change_ix,
+                &[
+                    temp_token_account.clone(),
+                    legacy_pda_account.clone(),
+                    token_program.clone(),
+                ],
+                &[&[Escrow::AUTHORITY_SEED, &[legacy_bump_seed]]],
+            )?;
+        }
+
+        //version < 4: the decimals weren't stored yet
+        if version < 4 {
+            if *offered_mint_account.key != escrow_info.offered_mint {
+                return Err(EscrowError::OfferedMintMismatch.into());
+            }
+            if *requested_mint_account.key != escrow_info.requested_mint {
+                return Err(EscrowError::RequestedMintMismatch.into());
+            }
+            escrow_info.offered_decimals = token::unpack_mint(offered_mint_account)?.decimals;
+            escrow_info.requested_decimals = token::unpack_mint(requested_mint_account)?.decimals;
+        }
+
+        //top up the rent for the bigger account before growing it
+        let rent = Rent::get()?;
+        let required_lamports = rent
+            .minimum_balance(Escrow::LEN)
+            .saturating_sub(escrow_account.lamports());
+        if required_lamports > 0 {
+            invoke(
+                &system_instruction::transfer(payer.key, escrow_account.key, required_lamports),
+                &[
+                    payer.clone(),
+                    escrow_account.clone(),
+                    system_program.clone(),
+                ],
+            )?;
+        }
+
+        escrow_account.realloc(Escrow::LEN, true)?;
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    //the protocol fee may only go to a token account of the right mint owned by the fee owner. A leg without a fee
+    //never touches its fee account, so any account will do then (fees are optional, the protocol needn't hold every mint)
+    fn check_fee_account(
+        fee_account: &AccountInfo,
+        fee: u64,
+        mint: &Pubkey,
+        fee_owner: &Pubkey,
+    ) -> ProgramResult {
+        if fee == 0 {
+            return Ok(());
+        }
+        //SOL fees go straight to the fee owner's wallet
+        if native::is_native(mint) {
+            if fee_account.key != fee_owner {
+                return Err(EscrowError::InvalidFeeAccount.into());
+            }
+            return Ok(());
+        }
+        let fee_account_info = token::unpack_account(fee_account)?;
+        if fee_account_info.mint != *mint || fee_account_info.owner != *fee_owner {
+            return Err(EscrowError::InvalidFeeAccount.into());
+        }
+        Ok(())
+    }
+
+    //recreates the escrow's PDA from the bump stored at initialization (much cheaper than find_program_address)
+    fn authority_address(
+        escrow_pubkey: &Pubkey,
+        bump_seed: u8,
+        program_id: &Pubkey,
+    ) -> Result<Pubkey, ProgramError> {
+        Pubkey::create_program_address(
+            &[Escrow::AUTHORITY_SEED, escrow_pubkey.as_ref(), &[bump_seed]],
+            program_id,
+        )
+        .map_err(|_| ProgramError::InvalidSeeds)
+    }
+
+    fn process_initialize_config(
+        accounts: &[AccountInfo],
+        admin: Pubkey,
+        fee_owner: Pubkey,
+        fee: Fee,
+        features: u64,
+        expire_bounty: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let upgrade_authority = next_account_info(account_info_iter)?;
+
+        if !upgrade_authority.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let config_account = next_account_info(account_info_iter)?;
+        let (config_address, bump_seed) = Config::find_address(program_id);
+        if *config_account.key != config_address {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        if config_account.data_len() > 0 {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        //whoever deployed the program decides who administers it, otherwise anyone could grab the config first
+        let program_data = next_account_info(account_info_iter)?;
+        if Self::upgrade_authority(program_data, program_id)? != Some(*upgrade_authority.key) {
+            return Err(EscrowError::Unauthorized.into());
+        }
+
+        if !fee.is_valid() {
+            return Err(EscrowError::InvalidFee.into());
+        }
+
+        let system_program = next_account_info(account_info_iter)?;
+        msg!("Calling the system program to create the config account...");
+        Self::create_pda_account(
+            upgrade_authority,
+            config_account,
+            system_program,
+            Config::LEN,
+            program_id,
+            &[Config::SEED, &[bump_seed]],
+        )?;
+
+        let config = Config {
+            is_initialized: true,
+            admin,
+            fee_owner,
+            fee,
+            features,
+            bump_seed,
+            paused: false,
+            expire_bounty,
+        };
+        Config::pack(config, &mut config_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_update_config(
+        accounts: &[AccountInfo],
+        admin: Pubkey,
+        fee_owner: Pubkey,
+        fee: Fee,
+        features: u64,
+        expire_bounty: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let current_admin = next_account_info(account_info_iter)?;
+
+        if !current_admin.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let config_account = next_account_info(account_info_iter)?;
+        let mut config = Self::load_config(config_account, program_id)?;
+        if config.admin != *current_admin.key {
+            return Err(EscrowError::Unauthorized.into());
+        }
+
+        if !fee.is_valid() {
+            return Err(EscrowError::InvalidFee.into());
+        }
+
+        config.admin = admin;
+        config.fee_owner = fee_owner;
+        config.fee = fee;
+        config.features = features;
+        config.expire_bounty = expire_bounty;
+        Config::pack(config, &mut config_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_set_paused(
+        accounts: &[AccountInfo],
+        paused: bool,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let admin = next_account_info(account_info_iter)?;
+
+        if !admin.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let config_account = next_account_info(account_info_iter)?;
+        let mut config = Self::load_config(config_account, program_id)?;
+        if config.admin != *admin.key {
+            return Err(EscrowError::Unauthorized.into());
+        }
+
+        config.paused = paused;
+        Config::pack(config, &mut config_account.try_borrow_mut_data()?)?;
+        msg!("Paused: {}", paused);
+
+        Ok(())
+    }
+
+    //unpacks the program's config account after making sure it really is the config PDA
+    fn load_config(
+        config_account: &AccountInfo,
+        program_id: &Pubkey,
+    ) -> Result<Config, ProgramError> {
+        if config_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let config = Config::unpack(&config_account.try_borrow_data()?)?;
+        let config_address =
+            Pubkey::create_program_address(&[Config::SEED, &[config.bump_seed]], program_id)
+                .map_err(|_| ProgramError::InvalidSeeds)?;
+        if *config_account.key != config_address {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        Ok(config)
+    }
+
+    //reads the upgrade authority out of this program's ProgramData account
+    //(bincode layout: u32 enum tag 3, u64 deployment slot, Option<Pubkey> upgrade authority)
+    fn upgrade_authority(
+        program_data: &AccountInfo,
+        program_id: &Pubkey,
+    ) -> Result<Option<Pubkey>, ProgramError> {
+        let (program_data_address, _bump_seed) =
+            Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+        if *program_data.key != program_data_address
+            || *program_data.owner != bpf_loader_upgradeable::id()
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let data = program_data.try_borrow_data()?;
+        let metadata = data.get(..45).ok_or(ProgramError::InvalidAccountData)?;
+        let (tag, _slot, authority) = array_refs![array_ref![metadata, 0, 45], 4, 8, 33];
+        if u32::from_le_bytes(*tag) != 3 {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(match authority[0] {
+            0 => None,
+            _ => Some(Pubkey::new_from_array(*array_ref![authority, 1, 32])),
+        })
+    }
+
+    //closes an account of this program for good, its lamports go to `destination`. Zeroing the lamports alone leaves the
+    //data and owner in place until the transaction ends, so a later instruction could pay the rent back and revive the
+    //account as it was (a delegated escrow could then be taken twice). Instead the data is wiped and dropped and the
+    //account handed back to the system program, so a revived one is an empty system account that fails our owner checks
+    fn close_program_account(account: &AccountInfo, destination: &AccountInfo) -> ProgramResult {
+        native::withdraw(account, destination, account.lamports())?;
+        account.try_borrow_mut_data()?.fill(0);
+        account.realloc(0, false)?;
+        account.assign(&system_program::ID);
+        Ok(())
+    }
+
+    //creates an account at a PDA of this program, also works when someone already sent lamports to the address
+    fn create_pda_account<'a>(
+        payer: &AccountInfo<'a>,
+        new_account: &AccountInfo<'a>,
+        system_program: &AccountInfo<'a>,
+        space: usize,
+        owner: &Pubkey,
+        signer_seeds: &[&[u8]],
+    ) -> ProgramResult {
+        let rent = Rent::get()?;
+        let required_lamports = rent.minimum_balance(space);
+
+        if new_account.lamports() == 0 {
+            return invoke_signed(
+                &system_instruction::create_account(
+                    payer.key,
+                    new_account.key,
+                    required_lamports,
+                    space as u64,
+                    owner,
+                ),
+                &[payer.clone(), new_account.clone(), system_program.clone()],
+                &[signer_seeds],
+            );
+        }
 
+        //create_account refuses accounts that already hold lamports, so do its three steps by hand
+        let missing_lamports = required_lamports.saturating_sub(new_account.lamports());
+        if missing_lamports > 0 {
+            invoke(
+                &system_instruction::transfer(payer.key, new_account.key, missing_lamports),
+                &[payer.clone(), new_account.clone(), system_program.clone()],
+            )?;
+        }
+        invoke_signed(
+            &system_instruction::allocate(new_account.key, space as u64),
+            &[new_account.clone(), system_program.clone()],
+            &[signer_seeds],
+        )?;
+        invoke_signed(
+            &system_instruction::assign(new_account.key, owner),
+            &[new_account.clone(), system_program.clone()],
+            &[signer_seeds],
+        )
+    }
+
+    //unpacks a token account and makes sure it holds tokens of the given mint, returning `error` otherwise
+    fn check_mint(token_account: &AccountInfo, mint: &Pubkey, error: EscrowError) -> ProgramResult {
+        let token_account_info = token::unpack_account(token_account)?;
+        if token_account_info.mint != *mint {
+            return Err(error.into());
+        }
         Ok(())
     }
+
+    //price of `amount_taken` out of `total_amount` offered tokens when all of them cost `expected_amount`,
+    //rounded up so rounding always favours the initializer
+    fn pro_rata_amount(
+        expected_amount: u64,
+        amount_taken: u64,
+        total_amount: u64,
+    ) -> Result<u64, ProgramError> {
+        let amount = (expected_amount as u128)
+            .checked_mul(amount_taken as u128)
+            .and_then(|product| product.checked_add(total_amount as u128 - 1))
+            .and_then(|product| product.checked_div(total_amount as u128))
+            .ok_or(EscrowError::AmountOverflow)?;
+        u64::try_from(amount).map_err(|_| EscrowError::AmountOverflow.into())
+    }
 }
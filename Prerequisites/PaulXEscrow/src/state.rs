@@ -5,7 +5,6 @@
     2) serializing and deserializing such objects from and into arrays of u8 respectively.
 */
 use solana_program::{
-    clock::Clock,
     program_error::ProgramError,
     program_pack::{IsInitialized, Pack, Sealed},
     pubkey::Pubkey,
@@ -13,6 +12,11 @@ use solana_program::{
 
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
 
+use crate::{
+    auction::{Bid, DutchAuction, EnglishAuction},
+    fee::Fee,
+};
+
 pub struct Escrow {
     pub is_initialized: bool, //determine whether a given escrow account already in use
     pub initializer_pubkey: Pubkey,
@@ -27,9 +31,103 @@ pub struct Escrow {
     //Used to make sure Bob send's enough of his tokens (no cheating!)
     pub expected_amount: u64,
 
-    //unlock and lock times
+    //unlock and lock times (unix timestamps in seconds), the trade can only be taken in between
     pub unlock_time: u64,
     pub time_out: u64,
+
+    //bump of the PDA derived from this escrow account's key, the PDA owns the temp token account of this escrow only
+    pub bump_seed: u8,
+
+    //mint of the tokens Alice offers (held in the temp token account) and of the tokens she wants in return
+    pub offered_mint: Pubkey,
+    pub requested_mint: Pubkey,
+
+    //the only account allowed to take a private trade, None (stored as zeroes) lets anyone take it
+    pub taker: Option<Pubkey>,
+
+    //decimals of both mints when the trade was set up, every transfer is checked against them
+    pub offered_decimals: u8,
+    pub requested_decimals: u8,
+
+    //price schedule of a Dutch auction, the expected amount is then the floor the price falls to. None is a fixed price trade
+    pub dutch_auction: Option<DutchAuction>,
+
+    //rules of an English auction, the expected amount is then the reserve price. None for every other kind of trade
+    pub english_auction: Option<EnglishAuction>,
+    //token account of the requested token owned by the PDA, holds the highest bid of an English auction
+    pub bid_token_account_pubkey: Pubkey,
+    pub highest_bid: Option<Bid>,
+
+    //what's still offered by a delegated escrow, whose tokens stay in Alice's own token account (the temp token account key
+    //then points at it) until the initializer's delegate PDA pulls them. None when the escrow holds its tokens itself
+    pub delegated_amount: Option<u64>,
+}
+
+impl Escrow {
+    /// Layout version written by `pack_into_slice`, stored in the first byte of the account (0 means uninitialized).
+    /// Version 1 is the original 121 byte layout whose first byte was a plain `is_initialized` flag.
+    pub const VERSION: u8 = 7;
+
+    //account data length of every layout version so far, index 0 is version 1.
+    //New fields are only ever appended, so an older layout is always a prefix of the current one.
+    const VERSION_LENS: [usize; Self::VERSION as usize] = [121, 186, 218, 220, 253, 335, 344];
+
+    /// Returns the data length used by the given layout version
+    pub fn version_len(version: u8) -> Option<usize> {
+        Self::VERSION_LENS
+            .get((version as usize).checked_sub(1)?)
+            .copied()
+    }
+
+    /// Seed prefix of escrow accounts the program creates at an address derived from the initializer and a nonce
+    pub const ADDRESS_SEED: &'static [u8] = b"escrow_address";
+
+    /// Derives the address (and its bump) of the initializer's escrow account with the given nonce.
+    /// Clients find every escrow of a user by deriving the addresses of the nonces they used.
+    pub fn find_address(
+        initializer_pubkey: &Pubkey,
+        nonce: u64,
+        program_id: &Pubkey,
+    ) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[
+                Self::ADDRESS_SEED,
+                initializer_pubkey.as_ref(),
+                &nonce.to_le_bytes(),
+            ],
+            program_id,
+        )
+    }
+
+    /// Seed prefix of the PDA that holds authority over an escrow's temp token account
+    pub const AUTHORITY_SEED: &'static [u8] = b"escrow";
+
+    /// Derives the PDA (and its bump) that owns the temp token account of the given escrow account.
+    /// Every escrow gets its own PDA so a signature for one trade can never move another trade's tokens.
+    pub fn find_authority_address(escrow_pubkey: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[Self::AUTHORITY_SEED, escrow_pubkey.as_ref()], program_id)
+    }
+
+    /// Seed prefix of the PDA an initializer approves as delegate on their token accounts for delegated escrows
+    pub const DELEGATE_SEED: &'static [u8] = b"delegate";
+
+    /// Derives the delegate PDA (and its bump) of the given initializer. It's shared by all of their delegated escrows,
+    /// so one token account can back many offers with a single approval covering all of them.
+    pub fn find_delegate_address(initializer_pubkey: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[Self::DELEGATE_SEED, initializer_pubkey.as_ref()],
+            program_id,
+        )
+    }
+
+    /// Seed prefix of the vault token account the program creates for an escrow when the initializer deposits directly
+    pub const VAULT_SEED: &'static [u8] = b"vault";
+
+    /// Derives the address (and its bump) of the vault token account of the given escrow account.
+    /// The vault takes the place of the temp token account, so it's owned by the escrow's authority PDA.
+    pub fn find_vault_address(escrow_pubkey: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[Self::VAULT_SEED, escrow_pubkey.as_ref()], program_id)
+    }
 }
 
 impl Sealed for Escrow {} //Solana's version of Rust's Sized trait
@@ -41,53 +139,143 @@ impl IsInitialized for Escrow {
 }
 
 impl Pack for Escrow {
-    const LEN: usize = 121;
+    const LEN: usize = 344;
 
     //DESERIALIZATION OF STATE
+    //Pack::unpack only hands us slices of the current LEN, older versions reach this through MigrateEscrow calling it directly
     fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
         // unpack_from_slice --> static constructor function returning a new instance of an escrow struct (don't have access to self yet)
+        let version = *src.first().ok_or(ProgramError::InvalidAccountData)?;
+        let version_len = match version {
+            0 => src.len().min(Escrow::LEN),
+            _ => Escrow::version_len(version).ok_or(ProgramError::InvalidAccountData)?,
+        };
+        if src.len() < version_len {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        //older layouts are a prefix of the current one, the fields they don't have yet read as zero
+        let mut buf = [0u8; Escrow::LEN];
+        buf[..version_len].copy_from_slice(&src[..version_len]);
+
         //turns an array of u8 into an instance of the Escrow struct we defined above, uses array_ref (references to sections of an array)
-        let src = array_ref![src, 0, Escrow::LEN];
+        let src = array_ref![buf, 0, Escrow::LEN];
         let (
-            is_initialized,
+            _version,
             initializer_pubkey,
             temp_token_account_pubkey,
             initializer_token_to_receive_account_pubkey,
             expected_amount,
             unlock_time,
             time_out,
-        ) = array_refs![src, 1, 32, 32, 32, 8, 8, 8];
-        let is_initialized = match is_initialized {
-            [0] => false,
-            [1] => true,
-            _ => return Err(ProgramError::InvalidAccountData),
-        };
+            bump_seed,
+            offered_mint,
+            requested_mint,
+            taker,
+            offered_decimals,
+            requested_decimals,
+            dutch_auction,
+            english_auction,
+            bid_token_account_pubkey,
+            highest_bid,
+            delegated_amount,
+        ) = array_refs![src, 1, 32, 32, 32, 8, 8, 8, 1, 32, 32, 32, 1, 1, 33, 9, 32, 41, 9];
+        let (is_dutch_auction, start_amount, start_time, end_time, step) =
+            array_refs![dutch_auction, 1, 8, 8, 8, 8];
+        let (is_english_auction, min_increment) = array_refs![english_auction, 1, 8];
+        let (has_bid, bidder, bid_amount) = array_refs![highest_bid, 1, 32, 8];
+        let (is_delegated, delegated_amount) = array_refs![delegated_amount, 1, 8];
 
-        Ok(Escrow {
-            is_initialized,
+        let mut escrow = Escrow {
+            is_initialized: version != 0,
             initializer_pubkey: Pubkey::new_from_array(*initializer_pubkey),
             temp_token_account_pubkey: Pubkey::new_from_array(*temp_token_account_pubkey),
             initializer_token_to_receive_account_pubkey: Pubkey::new_from_array(
                 *initializer_token_to_receive_account_pubkey,
             ),
             expected_amount: u64::from_le_bytes(*expected_amount),
-            time_out: 100,
-            unlock_time: 1000,
-        })
+            unlock_time: u64::from_le_bytes(*unlock_time),
+            time_out: u64::from_le_bytes(*time_out),
+            bump_seed: bump_seed[0],
+            offered_mint: Pubkey::new_from_array(*offered_mint),
+            requested_mint: Pubkey::new_from_array(*requested_mint),
+            taker: match Pubkey::new_from_array(*taker) {
+                taker if taker == Pubkey::default() => None,
+                taker => Some(taker),
+            },
+            offered_decimals: offered_decimals[0],
+            requested_decimals: requested_decimals[0],
+            dutch_auction: match is_dutch_auction {
+                [0] => None,
+                [1] => Some(DutchAuction {
+                    start_amount: u64::from_le_bytes(*start_amount),
+                    start_time: u64::from_le_bytes(*start_time),
+                    end_time: u64::from_le_bytes(*end_time),
+                    step: u64::from_le_bytes(*step),
+                }),
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            english_auction: match is_english_auction {
+                [0] => None,
+                [1] => Some(EnglishAuction {
+                    min_increment: u64::from_le_bytes(*min_increment),
+                }),
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            bid_token_account_pubkey: Pubkey::new_from_array(*bid_token_account_pubkey),
+            highest_bid: match has_bid {
+                [0] => None,
+                [1] => Some(Bid {
+                    bidder: Pubkey::new_from_array(*bidder),
+                    amount: u64::from_le_bytes(*bid_amount),
+                }),
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            delegated_amount: match is_delegated {
+                [0] => None,
+                [1] => Some(u64::from_le_bytes(*delegated_amount)),
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+        };
+
+        //version 1 reserved the deadline bytes but never wrote them, those escrows never lock or time out
+        if version == 1 {
+            escrow.unlock_time = 0;
+            escrow.time_out = u64::MAX;
+        }
+
+        Ok(escrow)
     }
 
     //SERIALIZATION OF STATE (here was pass in a reference to the Escrow struct)
     fn pack_into_slice(&self, dst: &mut [u8]) {
         let dst = array_mut_ref![dst, 0, Escrow::LEN];
         let (
-            is_initialized_dst,
+            version_dst,
             initializer_pubkey_dst,
             temp_token_account_pubkey_dst,
             initializer_token_to_receive_account_pubkey_dst,
             expected_amount_dst,
-            unlock_time,
-            time_out,
-        ) = mut_array_refs![dst, 1, 32, 32, 32, 8, 8, 8];
+            unlock_time_dst,
+            time_out_dst,
+            bump_seed_dst,
+            offered_mint_dst,
+            requested_mint_dst,
+            taker_dst,
+            offered_decimals_dst,
+            requested_decimals_dst,
+            dutch_auction_dst,
+            english_auction_dst,
+            bid_token_account_pubkey_dst,
+            highest_bid_dst,
+            delegated_amount_dst,
+        ) = mut_array_refs![dst, 1, 32, 32, 32, 8, 8, 8, 1, 32, 32, 32, 1, 1, 33, 9, 32, 41, 9];
+        let (is_dutch_auction_dst, start_amount_dst, start_time_dst, end_time_dst, step_dst) =
+            mut_array_refs![dutch_auction_dst, 1, 8, 8, 8, 8];
+        let (is_english_auction_dst, min_increment_dst) =
+            mut_array_refs![english_auction_dst, 1, 8];
+        let (has_bid_dst, bidder_dst, bid_amount_dst) = mut_array_refs![highest_bid_dst, 1, 32, 8];
+        let (is_delegated_dst, delegated_amount_dst) = mut_array_refs![delegated_amount_dst, 1, 8];
 
         let Escrow {
             is_initialized,
@@ -95,16 +283,183 @@ impl Pack for Escrow {
             temp_token_account_pubkey,
             initializer_token_to_receive_account_pubkey,
             expected_amount,
-            time_out,
             unlock_time,
+            time_out,
+            bump_seed,
+            offered_mint,
+            requested_mint,
+            taker,
+            offered_decimals,
+            requested_decimals,
+            dutch_auction,
+            english_auction,
+            bid_token_account_pubkey,
+            highest_bid,
+            delegated_amount,
         } = self;
 
-        is_initialized_dst[0] = *is_initialized as u8;
+        version_dst[0] = if *is_initialized { Escrow::VERSION } else { 0 };
         initializer_pubkey_dst.copy_from_slice(initializer_pubkey.as_ref());
         temp_token_account_pubkey_dst.copy_from_slice(temp_token_account_pubkey.as_ref());
         initializer_token_to_receive_account_pubkey_dst
             .copy_from_slice(initializer_token_to_receive_account_pubkey.as_ref());
         *expected_amount_dst = expected_amount.to_le_bytes();
+        *unlock_time_dst = unlock_time.to_le_bytes();
+        *time_out_dst = time_out.to_le_bytes();
+        bump_seed_dst[0] = *bump_seed;
+        offered_mint_dst.copy_from_slice(offered_mint.as_ref());
+        requested_mint_dst.copy_from_slice(requested_mint.as_ref());
+        taker_dst.copy_from_slice(taker.unwrap_or_default().as_ref());
+        offered_decimals_dst[0] = *offered_decimals;
+        requested_decimals_dst[0] = *requested_decimals;
+        let auction = dutch_auction.unwrap_or_default();
+        is_dutch_auction_dst[0] = dutch_auction.is_some() as u8;
+        *start_amount_dst = auction.start_amount.to_le_bytes();
+        *start_time_dst = auction.start_time.to_le_bytes();
+        *end_time_dst = auction.end_time.to_le_bytes();
+        *step_dst = auction.step.to_le_bytes();
+        is_english_auction_dst[0] = english_auction.is_some() as u8;
+        *min_increment_dst = english_auction
+            .unwrap_or_default()
+            .min_increment
+            .to_le_bytes();
+        bid_token_account_pubkey_dst.copy_from_slice(bid_token_account_pubkey.as_ref());
+        let bid = highest_bid.unwrap_or_default();
+        has_bid_dst[0] = highest_bid.is_some() as u8;
+        bidder_dst.copy_from_slice(bid.bidder.as_ref());
+        *bid_amount_dst = bid.amount.to_le_bytes();
+        is_delegated_dst[0] = delegated_amount.is_some() as u8;
+        *delegated_amount_dst = delegated_amount.unwrap_or_default().to_le_bytes();
+    }
+}
+
+//program-wide settings, lives in a single PDA so policies can change without redeploying the program
+pub struct Config {
+    pub is_initialized: bool,
+
+    //the only key allowed to change the config
+    pub admin: Pubkey,
+
+    //wallet owning the token accounts the protocol fee is paid into
+    pub fee_owner: Pubkey,
+    pub fee: Fee,
+
+    //bit set of the Config::FEATURE_* toggles that are switched on
+    pub features: u64,
+
+    pub bump_seed: u8,
+
+    //emergency switch, while set no new escrows can be opened or taken
+    pub paused: bool,
+
+    //lamports paid to whoever expires a timed out escrow, out of the rent freed by closing it
+    pub expire_bounty: u64,
+}
+
+impl Config {
+    /// Seed of the config PDA
+    pub const SEED: &'static [u8] = b"config";
+
+    /// Layout version written by `pack_into_slice`, stored in the first byte of the account (0 means uninitialized)
+    pub const VERSION: u8 = 1;
+
+    /// Takers may fill an escrow partially
+    pub const FEATURE_PARTIAL_FILLS: u64 = 1 << 0;
+
+    /// Derives the address (and its bump) of the program's config account
+    pub fn find_address(program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[Self::SEED], program_id)
+    }
+
+    /// Whether the given FEATURE_* toggle is switched on
+    pub fn is_enabled(&self, feature: u64) -> bool {
+        self.features & feature == feature
+    }
+}
+
+impl Sealed for Config {}
+
+impl IsInitialized for Config {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for Config {
+    const LEN: usize = 87;
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let src = array_ref![src, 0, Config::LEN];
+        let (
+            version,
+            admin,
+            fee_owner,
+            offered_fee_bps,
+            requested_fee_bps,
+            features,
+            bump_seed,
+            paused,
+            expire_bounty,
+        ) = array_refs![src, 1, 32, 32, 2, 2, 8, 1, 1, 8];
+        let is_initialized = match version[0] {
+            0 => false,
+            Config::VERSION => true,
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+
+        Ok(Config {
+            is_initialized,
+            admin: Pubkey::new_from_array(*admin),
+            fee_owner: Pubkey::new_from_array(*fee_owner),
+            fee: Fee {
+                offered_bps: u16::from_le_bytes(*offered_fee_bps),
+                requested_bps: u16::from_le_bytes(*requested_fee_bps),
+            },
+            features: u64::from_le_bytes(*features),
+            bump_seed: bump_seed[0],
+            paused: match paused {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            expire_bounty: u64::from_le_bytes(*expire_bounty),
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let dst = array_mut_ref![dst, 0, Config::LEN];
+        let (
+            version_dst,
+            admin_dst,
+            fee_owner_dst,
+            offered_fee_bps_dst,
+            requested_fee_bps_dst,
+            features_dst,
+            bump_seed_dst,
+            paused_dst,
+            expire_bounty_dst,
+        ) = mut_array_refs![dst, 1, 32, 32, 2, 2, 8, 1, 1, 8];
+
+        let Config {
+            is_initialized,
+            admin,
+            fee_owner,
+            fee,
+            features,
+            bump_seed,
+            paused,
+            expire_bounty,
+        } = self;
+
+        version_dst[0] = if *is_initialized { Config::VERSION } else { 0 };
+        admin_dst.copy_from_slice(admin.as_ref());
+        fee_owner_dst.copy_from_slice(fee_owner.as_ref());
+        *offered_fee_bps_dst = fee.offered_bps.to_le_bytes();
+        *requested_fee_bps_dst = fee.requested_bps.to_le_bytes();
+        *features_dst = features.to_le_bytes();
+        bump_seed_dst[0] = *bump_seed;
+        paused_dst[0] = *paused as u8;
+        *expire_bounty_dst = expire_bounty.to_le_bytes();
     }
 }
 
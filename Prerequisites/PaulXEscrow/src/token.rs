@@ -0,0 +1,275 @@
+/*Token programs --> the escrow works with both the original token program and Token-2022
+
+Both programs share the base account and mint layouts, Token-2022 just appends extensions after them,
+so everything here unpacks through spl_token_2022 which handles either program's accounts.
+*/
+use solana_program::{
+    account_info::AccountInfo, clock::Clock, entrypoint::ProgramResult, msg,
+    program::invoke_signed, program_error::ProgramError, pubkey::Pubkey, sysvar::Sysvar,
+};
+use spl_token_2022::{
+    extension::{
+        transfer_fee::{TransferFeeAmount, TransferFeeConfig},
+        BaseStateWithExtensions, ExtensionType, StateWithExtensions,
+    },
+    state::{Account, Mint},
+};
+
+use crate::error::EscrowError;
+
+/// Whether the key is one of the token programs the escrow supports
+pub fn is_token_program(program_id: &Pubkey) -> bool {
+    *program_id == spl_token::id() || *program_id == spl_token_2022::id()
+}
+
+/// Makes sure the account is one of the supported token programs, so we never CPI into something else
+pub fn check_token_program(token_program: &AccountInfo) -> ProgramResult {
+    if !is_token_program(token_program.key) {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    Ok(())
+}
+
+/// Unpacks a token account of either token program (extensions are skipped)
+pub fn unpack_account(account: &AccountInfo) -> Result<Account, ProgramError> {
+    if !is_token_program(account.owner) {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    Ok(StateWithExtensions::<Account>::unpack(&account.try_borrow_data()?)?.base)
+}
+
+/// Unpacks a mint of either token program (extensions are skipped)
+pub fn unpack_mint(mint: &AccountInfo) -> Result<Mint, ProgramError> {
+    if !is_token_program(mint.owner) {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    Ok(StateWithExtensions::<Mint>::unpack(&mint.try_borrow_data()?)?.base)
+}
+
+/// Size of a token account of the mint, Token-2022 mints may require extensions on every account (like a transfer fee
+/// mint does for the withheld amount)
+pub fn account_len(mint: &AccountInfo) -> Result<usize, ProgramError> {
+    let data = mint.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<Mint>::unpack(&data)?;
+    let account_extensions =
+        ExtensionType::get_required_init_account_extensions(&mint_state.get_extension_types()?);
+    Ok(ExtensionType::get_account_len::<Account>(
+        &account_extensions,
+    ))
+}
+
+/// Creates the wallet's associated token account of the mint unless it already exists, the wallet pays the rent
+/// and has to have signed the transaction
+pub fn create_associated_account<'a>(
+    associated_token_program: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    account: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    wallet: &AccountInfo<'a>,
+) -> ProgramResult {
+    if *associated_token_program.key != spl_associated_token_account::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    //the instruction is built for the derived address, any other account wouldn't even be part of it
+    let address = spl_associated_token_account::get_associated_token_address_with_program_id(
+        wallet.key,
+        mint.key,
+        token_program.key,
+    );
+    if *account.key != address {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let create_ix =
+        spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+            wallet.key,
+            wallet.key,
+            mint.key,
+            token_program.key,
+        );
+    invoke_signed(
+        &create_ix,
+        &[
+            wallet.clone(),
+            account.clone(),
+            mint.clone(),
+            system_program.clone(),
+            token_program.clone(),
+            associated_token_program.clone(),
+        ],
+        &[],
+    )
+}
+
+/// Amount that has to be sent this epoch so that `amount` arrives after the mint's transfer fee
+pub fn amount_before_fee(mint: &AccountInfo, amount: u64) -> Result<u64, ProgramError> {
+    let data = mint.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<Mint>::unpack(&data)?;
+    let fee = match mint_state.get_extension::<TransferFeeConfig>() {
+        Ok(fee_config) => fee_config
+            .calculate_inverse_epoch_fee(Clock::get()?.epoch, amount)
+            .ok_or(EscrowError::AmountOverflow)?,
+        Err(_) => 0,
+    };
+    amount
+        .checked_add(fee)
+        .ok_or_else(|| EscrowError::AmountOverflow.into())
+}
+
+/// Moves tokens with transfer_checked, which Token-2022 requires for mints with a transfer fee.
+/// The token program fails the transfer if the mint doesn't have the given decimals.
+/// Pass no seeds when the authority signed the transaction itself.
+#[allow(clippy::too_many_arguments)]
+pub fn transfer<'a>(
+    token_program: &AccountInfo<'a>,
+    source: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    destination: &AccountInfo<'a>,
+    authority: &AccountInfo<'a>,
+    amount: u64,
+    decimals: u8,
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let transfer_ix = spl_token_2022::instruction::transfer_checked(
+        token_program.key,
+        source.key,
+        mint.key,
+        destination.key,
+        authority.key,
+        &[],
+        amount,
+        decimals,
+    )?;
+    invoke_signed(
+        &transfer_ix,
+        &[
+            source.clone(),
+            mint.clone(),
+            destination.clone(),
+            authority.clone(),
+            token_program.clone(),
+        ],
+        signer_seeds,
+    )
+}
+
+/// Initializes an allocated token account of the mint, initialize_account3 doesn't need the rent sysvar passed in
+pub fn initialize_account<'a>(
+    token_program: &AccountInfo<'a>,
+    account: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    owner: &Pubkey,
+) -> ProgramResult {
+    let init_ix = spl_token_2022::instruction::initialize_account3(
+        token_program.key,
+        account.key,
+        mint.key,
+        owner,
+    )?;
+    invoke_signed(
+        &init_ix,
+        &[account.clone(), mint.clone(), token_program.clone()],
+        &[],
+    )
+}
+
+/// Approves `delegate` to move `amount` tokens out of the owner's account (replacing any earlier approval),
+/// the owner has to have signed the transaction
+pub fn approve<'a>(
+    token_program: &AccountInfo<'a>,
+    account: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    delegate: &AccountInfo<'a>,
+    owner: &AccountInfo<'a>,
+    amount: u64,
+    decimals: u8,
+) -> ProgramResult {
+    let approve_ix = spl_token_2022::instruction::approve_checked(
+        token_program.key,
+        account.key,
+        mint.key,
+        delegate.key,
+        owner.key,
+        &[],
+        amount,
+        decimals,
+    )?;
+    invoke_signed(
+        &approve_ix,
+        &[
+            account.clone(),
+            mint.clone(),
+            delegate.clone(),
+            owner.clone(),
+            token_program.clone(),
+        ],
+        &[],
+    )
+}
+
+/// Removes the delegate of the owner's account, the owner has to have signed the transaction
+pub fn revoke<'a>(
+    token_program: &AccountInfo<'a>,
+    account: &AccountInfo<'a>,
+    owner: &AccountInfo<'a>,
+) -> ProgramResult {
+    let revoke_ix =
+        spl_token_2022::instruction::revoke(token_program.key, account.key, owner.key, &[])?;
+    invoke_signed(
+        &revoke_ix,
+        &[account.clone(), owner.clone(), token_program.clone()],
+        &[],
+    )
+}
+
+/// Closes a token account owned by a PDA. Token-2022 refuses to close accounts still holding withheld transfer
+/// fees, so those get harvested to the mint first (anyone may do that).
+pub fn close_account<'a>(
+    token_program: &AccountInfo<'a>,
+    account: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    destination: &AccountInfo<'a>,
+    authority: &AccountInfo<'a>,
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let withheld_amount = {
+        let data = account.try_borrow_data()?;
+        let account_state = StateWithExtensions::<Account>::unpack(&data)?;
+        account_state
+            .get_extension::<TransferFeeAmount>()
+            .map(|fee_amount| u64::from(fee_amount.withheld_amount))
+            .unwrap_or(0)
+    };
+    if withheld_amount > 0 {
+        let harvest_ix =
+            spl_token_2022::extension::transfer_fee::instruction::harvest_withheld_tokens_to_mint(
+                token_program.key,
+                mint.key,
+                &[account.key],
+            )?;
+        msg!("Calling the token program to harvest the withheld transfer fees...");
+        invoke_signed(
+            &harvest_ix,
+            &[mint.clone(), account.clone(), token_program.clone()],
+            &[],
+        )?;
+    }
+
+    let close_ix = spl_token_2022::instruction::close_account(
+        token_program.key,
+        account.key,
+        destination.key,
+        authority.key,
+        &[],
+    )?;
+    invoke_signed(
+        &close_ix,
+        &[
+            account.clone(),
+            destination.clone(),
+            authority.clone(),
+            token_program.clone(),
+        ],
+        signer_seeds,
+    )
+}
@@ -0,0 +1,2655 @@
+/*End-to-end tests, runs the whole program (and the real token program) inside a local bank, no validator needed*/
+
+use solana_escrow::{
+    auction::{Auction, DutchAuction, EnglishAuction},
+    error::EscrowError,
+    event::EscrowEvent,
+    fee::Fee,
+    instruction::{self, EscrowInstruction, ExchangeOptions, InitEscrowOptions},
+    processor::Processor,
+    state::{Config, Escrow},
+};
+use solana_program::{program_option::COption, program_pack::Pack, pubkey::Pubkey, system_program};
+use solana_program_test::{
+    processor, tokio, ProgramTest, ProgramTestBanksClientExt, ProgramTestContext,
+};
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+    clock::Clock,
+    instruction::{Instruction, InstructionError},
+    signature::{Keypair, Signer},
+    system_instruction,
+    transaction::{Transaction, TransactionError},
+};
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_token_2022::{
+    extension::{
+        transfer_fee::instruction::initialize_transfer_fee_config, BaseStateWithExtensions,
+        ExtensionType, StateWithExtensions,
+    },
+    state::{Account as TokenAccount, Mint},
+};
+
+const OFFERED_AMOUNT: u64 = 1_000;
+const EXPECTED_AMOUNT: u64 = 500;
+const TEST_FEE: Fee = Fee {
+    offered_bps: 10,
+    requested_bps: 30,
+};
+const TEST_EXPIRE_BOUNTY: u64 = 10_000;
+
+//which token program a test mint lives in
+#[derive(Clone, Copy)]
+enum TestMint {
+    Token,
+    //Token-2022 mint charging an uncapped transfer fee
+    Token2022 { transfer_fee_bps: u16 },
+}
+
+//everything a trade between Alice (initializer) and Bob (taker) needs
+struct Trade {
+    context: ProgramTestContext,
+    program_id: Pubkey,
+    alice: Keypair,
+    bob: Keypair,
+    admin: Keypair,
+    fee_owner: Pubkey,
+    mint_x: Pubkey,
+    mint_y: Pubkey,
+    token_program_x: Pubkey,
+    token_program_y: Pubkey,
+    alice_x: Pubkey,
+    alice_y: Pubkey,
+    bob_x: Pubkey,
+    bob_y: Pubkey,
+    fee_x: Pubkey,
+    fee_y: Pubkey,
+    temp: Keypair,
+    escrow: Keypair,
+    //set to make the next escrow private to that taker
+    taker: Option<Pubkey>,
+    //set to sell the next escrow by auction, English auctions also need Alice's bid token account
+    auction: Option<Auction>,
+    bid_token_account: Option<Pubkey>,
+}
+
+impl Trade {
+    //Alice owns X tokens and wants Y tokens, Bob the other way round
+    async fn new() -> Self {
+        Self::with_mints(TestMint::Token, TestMint::Token).await
+    }
+
+    async fn with_mints(x: TestMint, y: TestMint) -> Self {
+        let mut trade = Self::deploy(x, y).await;
+        let ix = trade.initialize_config_instruction();
+        trade.send(&[ix], &[]).await.unwrap();
+        trade
+    }
+
+    async fn new_without_config() -> Self {
+        Self::deploy(TestMint::Token, TestMint::Token).await
+    }
+
+    //a freshly deployed program, the payer is its upgrade authority
+    async fn deploy(x: TestMint, y: TestMint) -> Self {
+        let program_id = Pubkey::new_unique();
+        let program_test =
+            ProgramTest::new("solana_escrow", program_id, processor!(Processor::process));
+        let mut context = program_test.start_with_context().await;
+
+        let (program_data, _bump_seed) =
+            Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+        let rent = context.banks_client.get_rent().await.unwrap();
+        let program_data_account = AccountSharedData::new_data(
+            rent.minimum_balance(UpgradeableLoaderState::size_of_programdata_metadata()),
+            &UpgradeableLoaderState::ProgramData {
+                slot: 0,
+                upgrade_authority_address: Some(context.payer.pubkey()),
+            },
+            &bpf_loader_upgradeable::id(),
+        )
+        .unwrap();
+        context.set_account(&program_data, &program_data_account);
+
+        let alice = Keypair::new();
+        let bob = Keypair::new();
+        let fee_owner = Pubkey::new_unique();
+        let mint_x = create_mint(&mut context, x).await;
+        let mint_y = create_mint(&mut context, y).await;
+        let alice_x = create_token_account(&mut context, &mint_x, &alice.pubkey()).await;
+        let alice_y = create_token_account(&mut context, &mint_y, &alice.pubkey()).await;
+        let bob_x = create_token_account(&mut context, &mint_x, &bob.pubkey()).await;
+        let bob_y = create_token_account(&mut context, &mint_y, &bob.pubkey()).await;
+        let fee_x = create_token_account(&mut context, &mint_x, &fee_owner).await;
+        let fee_y = create_token_account(&mut context, &mint_y, &fee_owner).await;
+        mint_to(&mut context, &mint_x, &alice_x, OFFERED_AMOUNT).await;
+        mint_to(&mut context, &mint_y, &bob_y, EXPECTED_AMOUNT).await;
+        let token_program_x = token_program(&mut context, &mint_x).await;
+        let token_program_y = token_program(&mut context, &mint_y).await;
+
+        Trade {
+            context,
+            program_id,
+            alice,
+            bob,
+            admin: Keypair::new(),
+            fee_owner,
+            mint_x,
+            mint_y,
+            token_program_x,
+            token_program_y,
+            alice_x,
+            alice_y,
+            bob_x,
+            bob_y,
+            fee_x,
+            fee_y,
+            temp: Keypair::new(),
+            escrow: Keypair::new(),
+            taker: None,
+            auction: None,
+            bid_token_account: None,
+        }
+    }
+
+    fn initialize_config_instruction(&self) -> Instruction {
+        instruction::initialize_config(
+            &self.program_id,
+            &self.context.payer.pubkey(),
+            &self.admin.pubkey(),
+            &self.fee_owner,
+            TEST_FEE,
+            Config::FEATURE_PARTIAL_FILLS,
+            TEST_EXPIRE_BOUNTY,
+        )
+    }
+
+    async fn config_info(&mut self) -> Config {
+        let config = Config::find_address(&self.program_id).0;
+        let account = self.account(&config).await.unwrap();
+        Config::unpack(&account.data).unwrap()
+    }
+
+    //the instructions a client sends to open the escrow: fund a temp account, create the escrow account, InitEscrow
+    async fn init_escrow_instructions(
+        &mut self,
+        unlock_time: u64,
+        time_out: u64,
+    ) -> Vec<Instruction> {
+        let payer = self.context.payer.pubkey();
+        let rent = self.context.banks_client.get_rent().await.unwrap();
+        let temp_len = token_account_len(&mut self.context, &self.mint_x).await;
+        vec![
+            system_instruction::create_account(
+                &payer,
+                &self.temp.pubkey(),
+                rent.minimum_balance(temp_len),
+                temp_len as u64,
+                &self.token_program_x,
+            ),
+            spl_token_2022::instruction::initialize_account(
+                &self.token_program_x,
+                &self.temp.pubkey(),
+                &self.mint_x,
+                &self.alice.pubkey(),
+            )
+            .unwrap(),
+            spl_token_2022::instruction::transfer_checked(
+                &self.token_program_x,
+                &self.alice_x,
+                &self.mint_x,
+                &self.temp.pubkey(),
+                &self.alice.pubkey(),
+                &[],
+                OFFERED_AMOUNT,
+                0,
+            )
+            .unwrap(),
+            system_instruction::create_account(
+                &payer,
+                &self.escrow.pubkey(),
+                rent.minimum_balance(Escrow::LEN),
+                Escrow::LEN as u64,
+                &self.program_id,
+            ),
+            instruction::init_escrow(
+                &self.program_id,
+                &self.token_program_x,
+                &self.alice.pubkey(),
+                &self.temp.pubkey(),
+                &self.alice_y,
+                &self.escrow.pubkey(),
+                &self.mint_x,
+                &self.mint_y,
+                EXPECTED_AMOUNT,
+                unlock_time,
+                time_out,
+                InitEscrowOptions {
+                    taker: self.taker,
+                    auction: self.auction,
+                    bid_token_account: self
+                        .bid_token_account
+                        .map(|bid_token_account| (bid_token_account, self.token_program_y)),
+                    ..InitEscrowOptions::default()
+                },
+            ),
+        ]
+    }
+
+    async fn init_escrow(&mut self) {
+        let instructions = self.init_escrow_instructions(0, u64::MAX).await;
+        let temp = self.temp.insecure_clone();
+        let escrow = self.escrow.insecure_clone();
+        let alice = self.alice.insecure_clone();
+        self.send(&instructions, &[&alice, &temp, &escrow])
+            .await
+            .unwrap();
+    }
+
+    fn exchange_instruction(&self, amount: u64) -> Instruction {
+        instruction::exchange(
+            &self.program_id,
+            &self.token_program_x,
+            &self.token_program_y,
+            &self.bob.pubkey(),
+            &self.bob_y,
+            &self.bob_x,
+            &self.temp.pubkey(),
+            &self.alice.pubkey(),
+            &self.alice_y,
+            &self.escrow.pubkey(),
+            &self.mint_x,
+            &self.mint_y,
+            &self.fee_x,
+            &self.fee_y,
+            amount,
+            ExchangeOptions::default(),
+        )
+    }
+
+    async fn exchange(&mut self, amount: u64) -> Result<(), TransactionError> {
+        let ix = self.exchange_instruction(amount);
+        let bob = self.bob.insecure_clone();
+        self.send(&[ix], &[&bob]).await
+    }
+
+    fn expire_instruction(&self, caller: &Pubkey) -> Instruction {
+        instruction::expire(
+            &self.program_id,
+            &self.token_program_x,
+            caller,
+            &self.temp.pubkey(),
+            &self.alice_x,
+            &self.alice.pubkey(),
+            &self.escrow.pubkey(),
+            &self.mint_x,
+            false,
+        )
+    }
+
+    fn place_bid_instruction(
+        &self,
+        bidder: &Pubkey,
+        bidders_token_account: &Pubkey,
+        previous_bidders_token_account: &Pubkey,
+        amount: u64,
+    ) -> Instruction {
+        instruction::place_bid(
+            &self.program_id,
+            &self.token_program_y,
+            bidder,
+            bidders_token_account,
+            &self.escrow.pubkey(),
+            &self.bid_token_account.unwrap(),
+            previous_bidders_token_account,
+            &self.mint_y,
+            amount,
+        )
+    }
+
+    fn settle_auction_instruction(&self, recipients_token_account: &Pubkey) -> Instruction {
+        instruction::settle_auction(
+            &self.program_id,
+            &self.token_program_x,
+            &self.token_program_y,
+            &self.context.payer.pubkey(),
+            &self.temp.pubkey(),
+            &self.bid_token_account.unwrap(),
+            recipients_token_account,
+            &self.alice_y,
+            &self.alice.pubkey(),
+            &self.escrow.pubkey(),
+            &self.mint_x,
+            &self.mint_y,
+            &self.fee_x,
+            &self.fee_y,
+        )
+    }
+
+    //at 1_000 Alice puts her X tokens up for English auction until 2_000, bids start at EXPECTED_AMOUNT and go up in steps of 50
+    async fn init_english_auction(&mut self) {
+        self.set_unix_timestamp(1_000).await;
+        let alice = self.alice.pubkey();
+        let bid_token_account = create_token_account(&mut self.context, &self.mint_y, &alice).await;
+        self.bid_token_account = Some(bid_token_account);
+        self.auction = Some(Auction::English(EnglishAuction { min_increment: 50 }));
+        let instructions = self.init_escrow_instructions(0, 2_000).await;
+        let temp = self.temp.insecure_clone();
+        let escrow = self.escrow.insecure_clone();
+        let alice = self.alice.insecure_clone();
+        self.send(&instructions, &[&alice, &temp, &escrow])
+            .await
+            .unwrap();
+    }
+
+    fn cancel_instruction(&self) -> Instruction {
+        instruction::cancel(
+            &self.program_id,
+            &self.token_program_x,
+            &self.alice.pubkey(),
+            &self.temp.pubkey(),
+            &self.alice_x,
+            &self.escrow.pubkey(),
+            &self.mint_x,
+            false,
+        )
+    }
+
+    //signs with the payer plus the given signers and waits for the transaction
+    async fn send(
+        &mut self,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> Result<(), TransactionError> {
+        let mut all_signers = vec![&self.context.payer];
+        all_signers.extend_from_slice(signers);
+        let blockhash = self
+            .context
+            .banks_client
+            .get_new_latest_blockhash(&self.context.last_blockhash)
+            .await
+            .unwrap();
+        self.context.last_blockhash = blockhash;
+        let transaction = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&self.context.payer.pubkey()),
+            &all_signers,
+            blockhash,
+        );
+        self.context
+            .banks_client
+            .process_transaction(transaction)
+            .await
+            .map_err(|e| e.unwrap())
+    }
+
+    async fn account(&mut self, pubkey: &Pubkey) -> Option<Account> {
+        self.context
+            .banks_client
+            .get_account(*pubkey)
+            .await
+            .unwrap()
+    }
+
+    async fn balance(&mut self, token_account: Pubkey) -> u64 {
+        let account = self.account(&token_account).await.unwrap();
+        unpack_token_account(&account.data).amount
+    }
+
+    async fn escrow_info(&mut self) -> Escrow {
+        let escrow = self.escrow.pubkey();
+        let account = self.account(&escrow).await.unwrap();
+        Escrow::unpack(&account.data).unwrap()
+    }
+
+    async fn set_unix_timestamp(&mut self, unix_timestamp: i64) {
+        let mut clock: Clock = self.context.banks_client.get_sysvar().await.unwrap();
+        clock.unix_timestamp = unix_timestamp;
+        self.context.set_sysvar(&clock);
+    }
+}
+
+async fn create_mint(context: &mut ProgramTestContext, kind: TestMint) -> Pubkey {
+    let mint = Keypair::new();
+    let payer = context.payer.pubkey();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let (token_program, extensions) = match kind {
+        TestMint::Token => (spl_token::id(), vec![]),
+        TestMint::Token2022 { .. } => {
+            (spl_token_2022::id(), vec![ExtensionType::TransferFeeConfig])
+        }
+    };
+    let space = ExtensionType::get_account_len::<Mint>(&extensions);
+
+    let mut instructions = vec![system_instruction::create_account(
+        &payer,
+        &mint.pubkey(),
+        rent.minimum_balance(space),
+        space as u64,
+        &token_program,
+    )];
+    if let TestMint::Token2022 { transfer_fee_bps } = kind {
+        instructions.push(
+            initialize_transfer_fee_config(
+                &token_program,
+                &mint.pubkey(),
+                None,
+                None,
+                transfer_fee_bps,
+                u64::MAX,
+            )
+            .unwrap(),
+        );
+    }
+    instructions.push(
+        spl_token_2022::instruction::initialize_mint(
+            &token_program,
+            &mint.pubkey(),
+            &payer,
+            None,
+            0,
+        )
+        .unwrap(),
+    );
+
+    let transaction = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&payer),
+        &[&context.payer, &mint],
+        context.last_blockhash,
+    );
+    context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+    mint.pubkey()
+}
+
+//the token program owning the mint
+async fn token_program(context: &mut ProgramTestContext, mint: &Pubkey) -> Pubkey {
+    context
+        .banks_client
+        .get_account(*mint)
+        .await
+        .unwrap()
+        .unwrap()
+        .owner
+}
+
+//size of a token account of this mint, Token-2022 mints may require extensions on their accounts
+async fn token_account_len(context: &mut ProgramTestContext, mint: &Pubkey) -> usize {
+    let mint_account = context
+        .banks_client
+        .get_account(*mint)
+        .await
+        .unwrap()
+        .unwrap();
+    let mint_state = StateWithExtensions::<Mint>::unpack(&mint_account.data).unwrap();
+    let extensions = ExtensionType::get_required_init_account_extensions(
+        &mint_state.get_extension_types().unwrap(),
+    );
+    ExtensionType::get_account_len::<TokenAccount>(&extensions)
+}
+
+async fn create_token_account(
+    context: &mut ProgramTestContext,
+    mint: &Pubkey,
+    owner: &Pubkey,
+) -> Pubkey {
+    let account = Keypair::new();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let token_program = token_program(context, mint).await;
+    let space = token_account_len(context, mint).await;
+    let transaction = Transaction::new_signed_with_payer(
+        &[
+            system_instruction::create_account(
+                &context.payer.pubkey(),
+                &account.pubkey(),
+                rent.minimum_balance(space),
+                space as u64,
+                &token_program,
+            ),
+            spl_token_2022::instruction::initialize_account(
+                &token_program,
+                &account.pubkey(),
+                mint,
+                owner,
+            )
+            .unwrap(),
+        ],
+        Some(&context.payer.pubkey()),
+        &[&context.payer, &account],
+        context.last_blockhash,
+    );
+    context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+    account.pubkey()
+}
+
+async fn mint_to(context: &mut ProgramTestContext, mint: &Pubkey, account: &Pubkey, amount: u64) {
+    let token_program = token_program(context, mint).await;
+    let transaction = Transaction::new_signed_with_payer(
+        &[spl_token_2022::instruction::mint_to(
+            &token_program,
+            mint,
+            account,
+            &context.payer.pubkey(),
+            &[],
+            amount,
+        )
+        .unwrap()],
+        Some(&context.payer.pubkey()),
+        &[&context.payer],
+        context.last_blockhash,
+    );
+    context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+}
+
+//token accounts of either token program, extensions are skipped
+fn unpack_token_account(data: &[u8]) -> TokenAccount {
+    StateWithExtensions::<TokenAccount>::unpack(data)
+        .unwrap()
+        .base
+}
+
+fn escrow_error(index: u8, error: EscrowError) -> TransactionError {
+    TransactionError::InstructionError(index, InstructionError::Custom(error as u32))
+}
+
+fn program_error(index: u8, error: InstructionError) -> TransactionError {
+    TransactionError::InstructionError(index, error)
+}
+
+#[test]
+fn test_instruction_pack_unpack() {
+    let instructions = [
+        EscrowInstruction::InitEscrow {
+            amount: 42,
+            unlock_time: 7,
+            time_out: u64::MAX,
+            taker: None,
+            auction: None,
+            nonce: None,
+            deposit: None,
+            delegated_amount: None,
+        },
+        EscrowInstruction::InitEscrow {
+            amount: 42,
+            unlock_time: 0,
+            time_out: 1,
+            taker: Some(Pubkey::new_unique()),
+            auction: None,
+            nonce: None,
+            deposit: None,
+            delegated_amount: None,
+        },
+        EscrowInstruction::InitEscrow {
+            amount: 42,
+            unlock_time: 0,
+            time_out: 1,
+            taker: None,
+            auction: Some(Auction::Dutch(DutchAuction {
+                start_amount: 84,
+                start_time: 2,
+                end_time: 3,
+                step: 4,
+            })),
+            nonce: None,
+            deposit: None,
+            delegated_amount: None,
+        },
+        EscrowInstruction::InitEscrow {
+            amount: 42,
+            unlock_time: 0,
+            time_out: 1,
+            taker: Some(Pubkey::new_unique()),
+            auction: Some(Auction::Dutch(DutchAuction::default())),
+            nonce: None,
+            deposit: None,
+            delegated_amount: None,
+        },
+        EscrowInstruction::InitEscrow {
+            amount: 42,
+            unlock_time: 0,
+            time_out: 1,
+            taker: None,
+            auction: Some(Auction::English(EnglishAuction { min_increment: 5 })),
+            nonce: None,
+            deposit: None,
+            delegated_amount: None,
+        },
+        EscrowInstruction::InitEscrow {
+            amount: 42,
+            unlock_time: 0,
+            time_out: 1,
+            taker: Some(Pubkey::new_unique()),
+            auction: Some(Auction::English(EnglishAuction { min_increment: 5 })),
+            nonce: Some(6),
+            deposit: None,
+            delegated_amount: None,
+        },
+        EscrowInstruction::InitEscrow {
+            amount: 42,
+            unlock_time: 0,
+            time_out: 1,
+            taker: None,
+            auction: None,
+            nonce: Some(0),
+            deposit: None,
+            delegated_amount: None,
+        },
+        EscrowInstruction::InitEscrow {
+            amount: 42,
+            unlock_time: 0,
+            time_out: 1,
+            taker: Some(Pubkey::new_unique()),
+            auction: Some(Auction::Dutch(DutchAuction::default())),
+            nonce: Some(6),
+            deposit: Some(1_000),
+            delegated_amount: None,
+        },
+        EscrowInstruction::InitEscrow {
+            amount: 42,
+            unlock_time: 0,
+            time_out: 1,
+            taker: None,
+            auction: None,
+            nonce: Some(6),
+            deposit: None,
+            delegated_amount: Some(1_000),
+        },
+        EscrowInstruction::Exchange {
+            amount: 1,
+            max_requested_amount: None,
+            create_receive_account: false,
+        },
+        EscrowInstruction::Exchange {
+            amount: 1,
+            max_requested_amount: Some(2),
+            create_receive_account: false,
+        },
+        EscrowInstruction::Exchange {
+            amount: 1,
+            max_requested_amount: None,
+            create_receive_account: true,
+        },
+        EscrowInstruction::Exchange {
+            amount: 1,
+            max_requested_amount: Some(2),
+            create_receive_account: true,
+        },
+        EscrowInstruction::Cancel,
+        EscrowInstruction::MigrateEscrow,
+        EscrowInstruction::InitializeConfig {
+            admin: Pubkey::new_unique(),
+            fee_owner: Pubkey::new_unique(),
+            fee: TEST_FEE,
+            features: 3,
+            expire_bounty: 5_000,
+        },
+        EscrowInstruction::UpdateConfig {
+            admin: Pubkey::new_unique(),
+            fee_owner: Pubkey::new_unique(),
+            fee: Fee::default(),
+            features: 0,
+            expire_bounty: 0,
+        },
+        EscrowInstruction::SetPaused { paused: true },
+        EscrowInstruction::SetPaused { paused: false },
+        EscrowInstruction::Expire,
+        EscrowInstruction::UpdateExpectedAmount {
+            amount: 42,
+            deadlines: None,
+        },
+        EscrowInstruction::UpdateExpectedAmount {
+            amount: 42,
+            deadlines: Some((7, 8)),
+        },
+        EscrowInstruction::PlaceBid { amount: 9 },
+        EscrowInstruction::SettleAuction,
+    ];
+    for instruction in instructions {
+        assert_eq!(
+            EscrowInstruction::unpack(&instruction.pack()).unwrap(),
+            instruction
+        );
+    }
+    assert!(EscrowInstruction::unpack(&[]).is_err());
+    assert!(EscrowInstruction::unpack(&[1, 0, 0]).is_err());
+    assert!(EscrowInstruction::unpack(&[1, 0, 0, 0, 0, 0, 0, 0, 0, 2]).is_err());
+    assert!(EscrowInstruction::unpack(&[255]).is_err());
+    assert!(EscrowInstruction::unpack(&[6, 2]).is_err());
+    //a field may only be sent once
+    let taker = Pubkey::new_unique();
+    let data = EscrowInstruction::InitEscrow {
+        amount: 42,
+        unlock_time: 0,
+        time_out: 1,
+        taker: None,
+        auction: None,
+        nonce: Some(0),
+        deposit: None,
+        delegated_amount: None,
+    }
+    .pack();
+    let mut twice = data.clone();
+    twice.extend_from_within(25..);
+    assert!(EscrowInstruction::unpack(&twice).is_err());
+    //the taker has its own tag like every other field, so it may come after them
+    let mut taker_last = data;
+    taker_last.push(6);
+    taker_last.extend_from_slice(taker.as_ref());
+    assert_eq!(
+        EscrowInstruction::unpack(&taker_last).unwrap(),
+        EscrowInstruction::InitEscrow {
+            amount: 42,
+            unlock_time: 0,
+            time_out: 1,
+            taker: Some(taker),
+            auction: None,
+            nonce: Some(0),
+            deposit: None,
+            delegated_amount: None,
+        }
+    );
+}
+
+#[test]
+fn test_event_pack_unpack() {
+    let events = [
+        EscrowEvent::Created {
+            escrow: Pubkey::new_unique(),
+            initializer: Pubkey::new_unique(),
+            offered_mint: Pubkey::new_unique(),
+            requested_mint: Pubkey::new_unique(),
+            offered_amount: 1,
+            expected_amount: 2,
+            unlock_time: 3,
+            time_out: 4,
+        },
+        EscrowEvent::Exchanged {
+            escrow: Pubkey::new_unique(),
+            initializer: Pubkey::new_unique(),
+            taker: Pubkey::new_unique(),
+            offered_mint: Pubkey::new_unique(),
+            requested_mint: Pubkey::new_unique(),
+            offered_amount: 5,
+            requested_amount: 6,
+            remaining_amount: 7,
+            offered_fee: 1,
+            requested_fee: 2,
+        },
+        EscrowEvent::Cancelled {
+            escrow: Pubkey::new_unique(),
+            initializer: Pubkey::new_unique(),
+            offered_mint: Pubkey::new_unique(),
+            refunded_amount: 8,
+        },
+        EscrowEvent::Expired {
+            escrow: Pubkey::new_unique(),
+            initializer: Pubkey::new_unique(),
+            offered_mint: Pubkey::new_unique(),
+            refunded_amount: 9,
+        },
+        EscrowEvent::Updated {
+            escrow: Pubkey::new_unique(),
+            initializer: Pubkey::new_unique(),
+            expected_amount: 10,
+            unlock_time: 11,
+            time_out: 12,
+        },
+        EscrowEvent::BidPlaced {
+            escrow: Pubkey::new_unique(),
+            bidder: Pubkey::new_unique(),
+            amount: 13,
+        },
+    ];
+    for event in events {
+        let mut data = event.pack();
+        assert_eq!(data[0], solana_escrow::event::EVENT_VERSION);
+        assert_eq!(EscrowEvent::unpack(&data).unwrap(), event);
+        //fields added by later versions are skipped
+        data[0] += 1;
+        data.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(EscrowEvent::unpack(&data).unwrap(), event);
+        assert!(EscrowEvent::unpack(&data[..data.len() - 4]).is_err());
+    }
+    assert!(EscrowEvent::unpack(&[0, 0]).is_err());
+
+    //version 1 exchanges had no fee fields
+    let mut data = vec![1, 1];
+    data.extend_from_slice(&[7; 5 * 32]);
+    data.extend_from_slice(&[0; 3 * 8]);
+    assert!(matches!(
+        EscrowEvent::unpack(&data).unwrap(),
+        EscrowEvent::Exchanged {
+            offered_fee: 0,
+            requested_fee: 0,
+            ..
+        }
+    ));
+    assert!(EscrowEvent::unpack(&[1, 255]).is_err());
+}
+
+#[tokio::test]
+async fn test_initialize_config() {
+    let mut trade = Trade::new().await;
+
+    let config = trade.config_info().await;
+    assert!(config.is_initialized);
+    assert_eq!(config.admin, trade.admin.pubkey());
+    assert_eq!(config.fee_owner, trade.fee_owner);
+    assert_eq!(config.fee, TEST_FEE);
+    assert!(config.is_enabled(Config::FEATURE_PARTIAL_FILLS));
+    assert_eq!(config.expire_bounty, TEST_EXPIRE_BOUNTY);
+    assert_eq!(config.bump_seed, Config::find_address(&trade.program_id).1);
+
+    let ix = trade.initialize_config_instruction();
+    assert_eq!(
+        trade.send(&[ix], &[]).await.unwrap_err(),
+        program_error(0, InstructionError::AccountAlreadyInitialized)
+    );
+}
+
+#[tokio::test]
+async fn test_initialize_config_not_upgrade_authority() {
+    let mut trade = Trade::new_without_config().await;
+    let bob = trade.bob.insecure_clone();
+    trade
+        .send(
+            &[system_instruction::transfer(
+                &trade.context.payer.pubkey(),
+                &bob.pubkey(),
+                1_000_000_000,
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+
+    let ix = instruction::initialize_config(
+        &trade.program_id,
+        &bob.pubkey(),
+        &bob.pubkey(),
+        &bob.pubkey(),
+        Fee::default(),
+        0,
+        0,
+    );
+    assert_eq!(
+        trade.send(&[ix], &[&bob]).await.unwrap_err(),
+        escrow_error(0, EscrowError::Unauthorized)
+    );
+
+    //rates above 100% are refused
+    let mut ix = trade.initialize_config_instruction();
+    ix.data = EscrowInstruction::InitializeConfig {
+        admin: trade.admin.pubkey(),
+        fee_owner: trade.fee_owner,
+        fee: Fee {
+            offered_bps: 0,
+            requested_bps: 10_001,
+        },
+        features: 0,
+        expire_bounty: 0,
+    }
+    .pack();
+    assert_eq!(
+        trade.send(&[ix], &[]).await.unwrap_err(),
+        escrow_error(0, EscrowError::InvalidFee)
+    );
+}
+
+#[tokio::test]
+async fn test_update_config() {
+    let mut trade = Trade::new().await;
+    trade.init_escrow().await;
+    let admin = trade.admin.insecure_clone();
+    let bob = trade.bob.insecure_clone();
+
+    //only the admin may touch the config
+    let ix = instruction::update_config(
+        &trade.program_id,
+        &bob.pubkey(),
+        &bob.pubkey(),
+        &bob.pubkey(),
+        Fee::default(),
+        0,
+        0,
+    );
+    assert_eq!(
+        trade.send(&[ix], &[&bob]).await.unwrap_err(),
+        escrow_error(0, EscrowError::Unauthorized)
+    );
+
+    //switch the fee off and disable partial fills
+    let ix = instruction::update_config(
+        &trade.program_id,
+        &admin.pubkey(),
+        &admin.pubkey(),
+        &trade.fee_owner,
+        Fee::default(),
+        0,
+        0,
+    );
+    trade.send(&[ix], &[&admin]).await.unwrap();
+    let config = trade.config_info().await;
+    assert_eq!(config.fee, Fee::default());
+    assert!(!config.is_enabled(Config::FEATURE_PARTIAL_FILLS));
+
+    assert_eq!(
+        trade.exchange(300).await.unwrap_err(),
+        escrow_error(0, EscrowError::FeatureDisabled)
+    );
+
+    //without a fee no fee accounts are needed, Bob can pass whatever he likes
+    let (fee_x, fee_y) = (trade.fee_x, trade.fee_y);
+    trade.fee_x = bob.pubkey();
+    trade.fee_y = bob.pubkey();
+    trade.exchange(OFFERED_AMOUNT).await.unwrap();
+    assert_eq!(trade.balance(trade.alice_y).await, EXPECTED_AMOUNT);
+    assert_eq!(trade.balance(trade.bob_x).await, OFFERED_AMOUNT);
+    assert_eq!(trade.balance(fee_x).await, 0);
+    assert_eq!(trade.balance(fee_y).await, 0);
+}
+
+#[tokio::test]
+async fn test_pause() {
+    let mut trade = Trade::new().await;
+    trade.init_escrow().await;
+    let admin = trade.admin.insecure_clone();
+    let bob = trade.bob.insecure_clone();
+
+    let ix = instruction::set_paused(&trade.program_id, &bob.pubkey(), true);
+    assert_eq!(
+        trade.send(&[ix], &[&bob]).await.unwrap_err(),
+        escrow_error(0, EscrowError::Unauthorized)
+    );
+
+    let ix = instruction::set_paused(&trade.program_id, &admin.pubkey(), true);
+    trade.send(&[ix], &[&admin]).await.unwrap();
+    assert!(trade.config_info().await.paused);
+
+    //no new trades and no fills
+    assert_eq!(
+        trade.exchange(OFFERED_AMOUNT).await.unwrap_err(),
+        escrow_error(0, EscrowError::ProgramPaused)
+    );
+
+    //a second escrow can't be opened either
+    mint_to(
+        &mut trade.context,
+        &trade.mint_x,
+        &trade.alice_x,
+        OFFERED_AMOUNT,
+    )
+    .await;
+    let escrow = std::mem::replace(&mut trade.escrow, Keypair::new());
+    let temp = std::mem::replace(&mut trade.temp, Keypair::new());
+    let instructions = trade.init_escrow_instructions(0, u64::MAX).await;
+    let alice = trade.alice.insecure_clone();
+    let other_temp = trade.temp.insecure_clone();
+    let other_escrow = trade.escrow.insecure_clone();
+    assert_eq!(
+        trade
+            .send(&instructions, &[&alice, &other_temp, &other_escrow])
+            .await
+            .unwrap_err(),
+        escrow_error(4, EscrowError::ProgramPaused)
+    );
+    trade.escrow = escrow;
+    trade.temp = temp;
+
+    //but Alice can still get her tokens back
+    let ix = trade.cancel_instruction();
+    trade.send(&[ix], &[&alice]).await.unwrap();
+    assert_eq!(trade.balance(trade.alice_x).await, 2 * OFFERED_AMOUNT);
+
+    let ix = instruction::set_paused(&trade.program_id, &admin.pubkey(), false);
+    trade.send(&[ix], &[&admin]).await.unwrap();
+    trade.temp = Keypair::new();
+    trade.escrow = Keypair::new();
+    trade.init_escrow().await;
+    trade.exchange(OFFERED_AMOUNT).await.unwrap();
+    assert_eq!(trade.balance(trade.bob_y).await, 0);
+}
+
+#[tokio::test]
+async fn test_init_escrow() {
+    let mut trade = Trade::new().await;
+    trade.init_escrow().await;
+
+    let escrow_info = trade.escrow_info().await;
+    let (pda, bump_seed) =
+        Escrow::find_authority_address(&trade.escrow.pubkey(), &trade.program_id);
+    assert!(escrow_info.is_initialized);
+    assert_eq!(escrow_info.initializer_pubkey, trade.alice.pubkey());
+    assert_eq!(escrow_info.temp_token_account_pubkey, trade.temp.pubkey());
+    assert_eq!(
+        escrow_info.initializer_token_to_receive_account_pubkey,
+        trade.alice_y
+    );
+    assert_eq!(escrow_info.expected_amount, EXPECTED_AMOUNT);
+    assert_eq!(escrow_info.bump_seed, bump_seed);
+    assert_eq!(escrow_info.offered_mint, trade.mint_x);
+    assert_eq!(escrow_info.requested_mint, trade.mint_y);
+    assert_eq!(escrow_info.taker, None);
+    assert_eq!(escrow_info.offered_decimals, 0);
+    assert_eq!(escrow_info.requested_decimals, 0);
+
+    //the PDA of this escrow now owns the temp token account
+    let temp = trade.temp.pubkey();
+    let temp_account = trade.account(&temp).await.unwrap();
+    let temp_info = unpack_token_account(&temp_account.data);
+    assert_eq!(temp_info.owner, pda);
+    assert_eq!(temp_info.amount, OFFERED_AMOUNT);
+    assert_eq!(temp_info.close_authority, COption::None);
+}
+
+#[tokio::test]
+async fn test_init_escrow_with_nonce() {
+    let mut trade = Trade::new().await;
+    let alice = trade.alice.insecure_clone();
+    let temp = trade.temp.insecure_clone();
+    let (escrow, _bump_seed) = Escrow::find_address(&alice.pubkey(), 7, &trade.program_id);
+    let init_ix = |nonce| {
+        instruction::init_escrow(
+            &trade.program_id,
+            &trade.token_program_x,
+            &alice.pubkey(),
+            &temp.pubkey(),
+            &trade.alice_y,
+            &escrow,
+            &trade.mint_x,
+            &trade.mint_y,
+            EXPECTED_AMOUNT,
+            0,
+            u64::MAX,
+            InitEscrowOptions {
+                nonce: Some(nonce),
+                ..InitEscrowOptions::default()
+            },
+        )
+    };
+    let wrong_nonce_ix = init_ix(8);
+    let init_ix = init_ix(7);
+
+    //no escrow account is created by the client, Alice pays for the one the program creates
+    let mut instructions = trade.init_escrow_instructions(0, u64::MAX).await;
+    instructions.remove(3);
+    instructions[3] = wrong_nonce_ix;
+    instructions.push(system_instruction::transfer(
+        &trade.context.payer.pubkey(),
+        &alice.pubkey(),
+        1_000_000_000,
+    ));
+    instructions.rotate_right(1);
+    assert_eq!(
+        trade
+            .send(&instructions, &[&alice, &temp])
+            .await
+            .unwrap_err(),
+        program_error(4, InstructionError::InvalidSeeds)
+    );
+    instructions[4] = init_ix.clone();
+    trade.send(&instructions, &[&alice, &temp]).await.unwrap();
+
+    let escrow_account = trade.account(&escrow).await.unwrap();
+    assert_eq!(escrow_account.owner, trade.program_id);
+    let escrow_info = Escrow::unpack(&escrow_account.data).unwrap();
+    assert_eq!(escrow_info.initializer_pubkey, alice.pubkey());
+    assert_eq!(escrow_info.temp_token_account_pubkey, temp.pubkey());
+
+    //the nonce is used up while the escrow is open
+    assert_eq!(
+        trade.send(&[init_ix], &[&alice]).await.unwrap_err(),
+        program_error(0, InstructionError::AccountAlreadyInitialized)
+    );
+
+    let ix = instruction::cancel(
+        &trade.program_id,
+        &trade.token_program_x,
+        &alice.pubkey(),
+        &temp.pubkey(),
+        &trade.alice_x,
+        &escrow,
+        &trade.mint_x,
+        false,
+    );
+    trade.send(&[ix], &[&alice]).await.unwrap();
+    assert_eq!(trade.balance(trade.alice_x).await, OFFERED_AMOUNT);
+    assert!(trade.account(&escrow).await.is_none());
+}
+
+#[tokio::test]
+async fn test_init_escrow_with_deposit() {
+    let mut trade = Trade::with_mints(
+        TestMint::Token2022 {
+            transfer_fee_bps: 100,
+        },
+        TestMint::Token,
+    )
+    .await;
+    let alice = trade.alice.insecure_clone();
+    let escrow = trade.escrow.insecure_clone();
+    let (vault, _bump_seed) = Escrow::find_vault_address(&escrow.pubkey(), &trade.program_id);
+    let init_ix = |temp: &Pubkey| {
+        instruction::init_escrow(
+            &trade.program_id,
+            &trade.token_program_x,
+            &alice.pubkey(),
+            temp,
+            &trade.alice_y,
+            &escrow.pubkey(),
+            &trade.mint_x,
+            &trade.mint_y,
+            EXPECTED_AMOUNT,
+            0,
+            u64::MAX,
+            InitEscrowOptions {
+                deposit: Some((500, trade.alice_x)),
+                ..InitEscrowOptions::default()
+            },
+        )
+    };
+    let wrong_vault_ix = init_ix(&trade.temp.pubkey());
+    let init_ix = init_ix(&vault);
+
+    //no temp token account, the client only creates the escrow account and Alice pays for the vault
+    let mut instructions = trade.init_escrow_instructions(0, u64::MAX).await;
+    instructions.drain(..3);
+    instructions[1] = wrong_vault_ix;
+    instructions.insert(
+        0,
+        system_instruction::transfer(
+            &trade.context.payer.pubkey(),
+            &alice.pubkey(),
+            1_000_000_000,
+        ),
+    );
+    assert_eq!(
+        trade
+            .send(&instructions, &[&alice, &escrow])
+            .await
+            .unwrap_err(),
+        program_error(2, InstructionError::InvalidSeeds)
+    );
+    instructions[2] = init_ix;
+    trade.send(&instructions, &[&alice, &escrow]).await.unwrap();
+
+    //the vault belongs to the escrow's PDA and holds the whole deposit, Alice paid the transfer fee on top
+    let (pda, _bump_seed) = Escrow::find_authority_address(&escrow.pubkey(), &trade.program_id);
+    let vault_account = trade.account(&vault).await.unwrap();
+    assert_eq!(vault_account.owner, trade.token_program_x);
+    assert_eq!(
+        vault_account.data.len(),
+        token_account_len(&mut trade.context, &trade.mint_x).await
+    );
+    let vault_info = unpack_token_account(&vault_account.data);
+    assert_eq!(vault_info.owner, pda);
+    assert_eq!(vault_info.mint, trade.mint_x);
+    assert_eq!(vault_info.amount, 500);
+    //the inverse fee is rounded up, 1% of 506 is 5.06 so the fee is 6
+    assert_eq!(trade.balance(trade.alice_x).await, OFFERED_AMOUNT - 506);
+    assert_eq!(trade.escrow_info().await.temp_token_account_pubkey, vault);
+
+    let ix = instruction::cancel(
+        &trade.program_id,
+        &trade.token_program_x,
+        &alice.pubkey(),
+        &vault,
+        &trade.alice_x,
+        &escrow.pubkey(),
+        &trade.mint_x,
+        false,
+    );
+    trade.send(&[ix], &[&alice]).await.unwrap();
+    assert!(trade.account(&vault).await.is_none());
+}
+
+#[tokio::test]
+async fn test_delegated_escrows() {
+    let mut trade = Trade::new().await;
+    let alice = trade.alice.insecure_clone();
+    let bob = trade.bob.insecure_clone();
+    let alice_x = trade.alice_x;
+    let payer = trade.context.payer.pubkey();
+    let rent = trade.context.banks_client.get_rent().await.unwrap();
+    let (delegate, _bump_seed) = Escrow::find_delegate_address(&alice.pubkey(), &trade.program_id);
+
+    //Alice backs two offers with the same token account: 600 X for 300 Y and 400 X for 200 Y
+    let escrows = [Keypair::new(), Keypair::new()];
+    for (escrow, offered_amount) in escrows.iter().zip([600, 400]) {
+        let instructions = [
+            system_instruction::create_account(
+                &payer,
+                &escrow.pubkey(),
+                rent.minimum_balance(Escrow::LEN),
+                Escrow::LEN as u64,
+                &trade.program_id,
+            ),
+            instruction::init_escrow(
+                &trade.program_id,
+                &trade.token_program_x,
+                &alice.pubkey(),
+                &trade.alice_x,
+                &trade.alice_y,
+                &escrow.pubkey(),
+                &trade.mint_x,
+                &trade.mint_y,
+                offered_amount / 2,
+                0,
+                u64::MAX,
+                InitEscrowOptions {
+                    delegated_amount: Some(offered_amount),
+                    ..InitEscrowOptions::default()
+                },
+            ),
+        ];
+        trade.send(&instructions, &[&alice, escrow]).await.unwrap();
+    }
+
+    //nothing moved, the delegate is approved for both offers together
+    let alice_x_info = unpack_token_account(&trade.account(&alice_x).await.unwrap().data);
+    assert_eq!(alice_x_info.amount, OFFERED_AMOUNT);
+    assert_eq!(alice_x_info.delegate, COption::Some(delegate));
+    assert_eq!(alice_x_info.delegated_amount, 1_000);
+
+    //a copy of Alice's escrow that belongs to another program
+    let forged_escrow = Keypair::new();
+    let escrow_account = trade.account(&escrows[0].pubkey()).await.unwrap();
+    let forged_account = Account {
+        owner: Pubkey::new_unique(),
+        ..escrow_account
+    };
+    trade
+        .context
+        .set_account(&forged_escrow.pubkey(), &forged_account.into());
+
+    let exchange_ix = |escrow: &Keypair, amount| {
+        instruction::exchange(
+            &trade.program_id,
+            &trade.token_program_x,
+            &trade.token_program_y,
+            &bob.pubkey(),
+            &trade.bob_y,
+            &trade.bob_x,
+            &trade.alice_x,
+            &alice.pubkey(),
+            &trade.alice_y,
+            &escrow.pubkey(),
+            &trade.mint_x,
+            &trade.mint_y,
+            &trade.fee_x,
+            &trade.fee_y,
+            amount,
+            ExchangeOptions {
+                delegated: true,
+                ..ExchangeOptions::default()
+            },
+        )
+    };
+    let first_half_ix = exchange_ix(&escrows[0], 300);
+    let second_half_ix = exchange_ix(&escrows[0], 300);
+    let too_much_ix = exchange_ix(&escrows[1], 401);
+    let forged_ix = exchange_ix(&forged_escrow, 300);
+
+    //a forged escrow can't get the delegate to pull Alice's approved tokens
+    assert_eq!(
+        trade.send(&[forged_ix], &[&bob]).await.unwrap_err(),
+        program_error(0, InstructionError::IncorrectProgramId)
+    );
+
+    //each offer can only be taken up to its own amount, even though the account holds more
+    assert_eq!(
+        trade.send(&[too_much_ix], &[&bob]).await.unwrap_err(),
+        escrow_error(0, EscrowError::ExpectedAmountMismatch)
+    );
+    trade.send(&[first_half_ix], &[&bob]).await.unwrap();
+    assert_eq!(trade.balance(trade.alice_x).await, 700);
+    let escrow = escrows[0].pubkey();
+    let escrow_info = Escrow::unpack(&trade.account(&escrow).await.unwrap().data).unwrap();
+    assert_eq!(escrow_info.delegated_amount, Some(300));
+    assert_eq!(escrow_info.expected_amount, 150);
+
+    //cancelling the other offer only takes back its share of the approval
+    let cancel_ix = instruction::cancel(
+        &trade.program_id,
+        &trade.token_program_x,
+        &alice.pubkey(),
+        &trade.alice_x,
+        &trade.alice_x,
+        &escrows[1].pubkey(),
+        &trade.mint_x,
+        true,
+    );
+    trade.send(&[cancel_ix], &[&alice]).await.unwrap();
+    assert!(trade.account(&escrows[1].pubkey()).await.is_none());
+    let alice_x_info = unpack_token_account(&trade.account(&alice_x).await.unwrap().data);
+    assert_eq!(alice_x_info.delegated_amount, 300);
+
+    //taking the rest closes the escrow but leaves Alice's account open
+    trade.send(&[second_half_ix], &[&bob]).await.unwrap();
+    assert!(trade.account(&escrow).await.is_none());
+    let alice_x_info = unpack_token_account(&trade.account(&alice_x).await.unwrap().data);
+    assert_eq!(alice_x_info.amount, 400);
+    assert_eq!(alice_x_info.delegate, COption::None);
+    //the protocol fee rounds down to nothing on these small amounts
+    assert_eq!(trade.balance(trade.alice_y).await, 300);
+}
+
+#[tokio::test]
+async fn test_closed_escrow_cannot_be_revived() {
+    let mut trade = Trade::new().await;
+    let alice = trade.alice.insecure_clone();
+    let bob = trade.bob.insecure_clone();
+    let payer = trade.context.payer.pubkey();
+    let rent = trade.context.banks_client.get_rent().await.unwrap();
+
+    //two delegated offers backed by the same token account, so the delegate's approval outlives the first one
+    let escrows = [Keypair::new(), Keypair::new()];
+    for escrow in &escrows {
+        let instructions = [
+            system_instruction::create_account(
+                &payer,
+                &escrow.pubkey(),
+                rent.minimum_balance(Escrow::LEN),
+                Escrow::LEN as u64,
+                &trade.program_id,
+            ),
+            instruction::init_escrow(
+                &trade.program_id,
+                &trade.token_program_x,
+                &alice.pubkey(),
+                &trade.alice_x,
+                &trade.alice_y,
+                &escrow.pubkey(),
+                &trade.mint_x,
+                &trade.mint_y,
+                EXPECTED_AMOUNT / 2,
+                0,
+                u64::MAX,
+                InitEscrowOptions {
+                    delegated_amount: Some(OFFERED_AMOUNT / 2),
+                    ..InitEscrowOptions::default()
+                },
+            ),
+        ];
+        trade.send(&instructions, &[&alice, escrow]).await.unwrap();
+    }
+
+    //taking the first offer closes its escrow, paying the rent back and taking it again in the same transaction must fail
+    let escrow = escrows[0].pubkey();
+    let exchange_ix = instruction::exchange(
+        &trade.program_id,
+        &trade.token_program_x,
+        &trade.token_program_y,
+        &bob.pubkey(),
+        &trade.bob_y,
+        &trade.bob_x,
+        &trade.alice_x,
+        &alice.pubkey(),
+        &trade.alice_y,
+        &escrow,
+        &trade.mint_x,
+        &trade.mint_y,
+        &trade.fee_x,
+        &trade.fee_y,
+        OFFERED_AMOUNT / 2,
+        ExchangeOptions {
+            delegated: true,
+            ..ExchangeOptions::default()
+        },
+    );
+    let revive_ix =
+        system_instruction::transfer(&payer, &escrow, rent.minimum_balance(Escrow::LEN));
+    let instructions = [exchange_ix.clone(), revive_ix.clone(), exchange_ix.clone()];
+    assert_eq!(
+        trade.send(&instructions, &[&bob]).await.unwrap_err(),
+        program_error(2, InstructionError::IncorrectProgramId)
+    );
+
+    //a revived account is an empty system account, nothing the program would take for an escrow
+    trade
+        .send(&[exchange_ix, revive_ix], &[&bob])
+        .await
+        .unwrap();
+    let account = trade.account(&escrow).await.unwrap();
+    assert_eq!(account.owner, system_program::id());
+    assert!(account.data.is_empty());
+    assert_eq!(trade.balance(trade.alice_x).await, OFFERED_AMOUNT / 2);
+}
+
+#[tokio::test]
+async fn test_init_escrow_twice() {
+    let mut trade = Trade::new().await;
+    trade.init_escrow().await;
+
+    let ix = instruction::init_escrow(
+        &trade.program_id,
+        &trade.token_program_x,
+        &trade.alice.pubkey(),
+        &trade.temp.pubkey(),
+        &trade.alice_y,
+        &trade.escrow.pubkey(),
+        &trade.mint_x,
+        &trade.mint_y,
+        EXPECTED_AMOUNT,
+        0,
+        u64::MAX,
+        InitEscrowOptions::default(),
+    );
+    let alice = trade.alice.insecure_clone();
+    assert_eq!(
+        trade.send(&[ix], &[&alice]).await.unwrap_err(),
+        program_error(0, InstructionError::AccountAlreadyInitialized)
+    );
+}
+
+#[tokio::test]
+async fn test_init_escrow_not_rent_exempt() {
+    let mut trade = Trade::new().await;
+    let mut instructions = trade.init_escrow_instructions(0, u64::MAX).await;
+
+    //the runtime won't let us create such an account, so put it into the bank directly
+    let rent = trade.context.banks_client.get_rent().await.unwrap();
+    trade.context.set_account(
+        &trade.escrow.pubkey(),
+        &Account {
+            lamports: rent.minimum_balance(Escrow::LEN) - 1,
+            data: vec![0; Escrow::LEN],
+            owner: trade.program_id,
+            ..Account::default()
+        }
+        .into(),
+    );
+    instructions.remove(3);
+
+    let alice = trade.alice.insecure_clone();
+    let temp = trade.temp.insecure_clone();
+    assert_eq!(
+        trade
+            .send(&instructions, &[&alice, &temp])
+            .await
+            .unwrap_err(),
+        escrow_error(3, EscrowError::NotRentExempt)
+    );
+}
+
+#[tokio::test]
+async fn test_init_escrow_invalid_times() {
+    let mut trade = Trade::new().await;
+    let instructions = trade.init_escrow_instructions(10, 10).await;
+    let alice = trade.alice.insecure_clone();
+    let temp = trade.temp.insecure_clone();
+    let escrow = trade.escrow.insecure_clone();
+    assert_eq!(
+        trade
+            .send(&instructions, &[&alice, &temp, &escrow])
+            .await
+            .unwrap_err(),
+        escrow_error(4, EscrowError::InvalidInstruction)
+    );
+}
+
+#[tokio::test]
+async fn test_init_escrow_missing_signer() {
+    let mut trade = Trade::new().await;
+    let mut instructions = trade.init_escrow_instructions(0, u64::MAX).await;
+    let mut init_ix = instructions.pop().unwrap();
+    init_ix.accounts[0].is_signer = false;
+
+    //Alice has to sign the token transfer into the temp account, so that goes into its own transaction
+    let alice = trade.alice.insecure_clone();
+    let temp = trade.temp.insecure_clone();
+    let escrow = trade.escrow.insecure_clone();
+    trade
+        .send(&instructions, &[&alice, &temp, &escrow])
+        .await
+        .unwrap();
+    assert_eq!(
+        trade.send(&[init_ix], &[]).await.unwrap_err(),
+        program_error(0, InstructionError::MissingRequiredSignature)
+    );
+}
+
+#[tokio::test]
+async fn test_exchange() {
+    let mut trade = Trade::new().await;
+    trade.init_escrow().await;
+    let alice = trade.alice.pubkey();
+    let temp = trade.temp.pubkey();
+    let escrow = trade.escrow.pubkey();
+    let escrow_lamports = trade.account(&escrow).await.unwrap().lamports;
+    let temp_lamports = trade.account(&temp).await.unwrap().lamports;
+
+    trade.exchange(OFFERED_AMOUNT).await.unwrap();
+
+    //the protocol fee is skimmed off both legs
+    let offered_fee = TEST_FEE.offered_fee(OFFERED_AMOUNT).unwrap();
+    let requested_fee = TEST_FEE.requested_fee(EXPECTED_AMOUNT).unwrap();
+    assert_eq!(
+        trade.balance(trade.alice_y).await,
+        EXPECTED_AMOUNT - requested_fee
+    );
+    assert_eq!(trade.balance(trade.fee_y).await, requested_fee);
+    assert_eq!(
+        trade.balance(trade.bob_x).await,
+        OFFERED_AMOUNT - offered_fee
+    );
+    assert_eq!(trade.balance(trade.fee_x).await, offered_fee);
+    assert_eq!(trade.balance(trade.bob_y).await, 0);
+
+    //both the temp token account and the escrow account are closed and their rent went to Alice
+    assert!(trade.account(&temp).await.is_none());
+    assert!(trade.account(&escrow).await.is_none());
+    assert_eq!(
+        trade.account(&alice).await.unwrap().lamports,
+        escrow_lamports + temp_lamports
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_creates_receive_account() {
+    let mut trade = Trade::new().await;
+    trade.init_escrow().await;
+    let bob = trade.bob.insecure_clone();
+    let bob_x = get_associated_token_address_with_program_id(
+        &bob.pubkey(),
+        &trade.mint_x,
+        &trade.token_program_x,
+    );
+    let exchange_ix = |takers_token_to_receive_account: &Pubkey| {
+        instruction::exchange(
+            &trade.program_id,
+            &trade.token_program_x,
+            &trade.token_program_y,
+            &bob.pubkey(),
+            &trade.bob_y,
+            takers_token_to_receive_account,
+            &trade.temp.pubkey(),
+            &trade.alice.pubkey(),
+            &trade.alice_y,
+            &trade.escrow.pubkey(),
+            &trade.mint_x,
+            &trade.mint_y,
+            &trade.fee_x,
+            &trade.fee_y,
+            OFFERED_AMOUNT / 2,
+            ExchangeOptions {
+                create_receive_account: true,
+                ..ExchangeOptions::default()
+            },
+        )
+    };
+    let not_associated_ix = exchange_ix(&trade.bob_x);
+    let exchange_ix = exchange_ix(&bob_x);
+    let fund_ix =
+        system_instruction::transfer(&trade.context.payer.pubkey(), &bob.pubkey(), 1_000_000_000);
+
+    //only the associated token account can be created
+    assert_eq!(
+        trade
+            .send(&[fund_ix.clone(), not_associated_ix], &[&bob])
+            .await
+            .unwrap_err(),
+        program_error(1, InstructionError::InvalidSeeds)
+    );
+
+    //Bob never held X before, the taker pays for the new account within the exchange
+    trade
+        .send(&[fund_ix, exchange_ix.clone()], &[&bob])
+        .await
+        .unwrap();
+    let offered_fee = TEST_FEE.offered_fee(OFFERED_AMOUNT / 2).unwrap();
+    assert_eq!(trade.balance(bob_x).await, OFFERED_AMOUNT / 2 - offered_fee);
+
+    //the account already exists the second time
+    trade.send(&[exchange_ix], &[&bob]).await.unwrap();
+    assert_eq!(trade.balance(bob_x).await, OFFERED_AMOUNT - 2 * offered_fee);
+}
+
+#[tokio::test]
+async fn test_offer_native_sol() {
+    let mut trade = Trade::new().await;
+    let alice = trade.alice.insecure_clone();
+    let bob = trade.bob.insecure_clone();
+    let escrow = trade.escrow.insecure_clone();
+    let payer = trade.context.payer.pubkey();
+    let rent = trade.context.banks_client.get_rent().await.unwrap();
+    let (vault, _bump_seed) = Escrow::find_vault_address(&escrow.pubkey(), &trade.program_id);
+    const LAMPORTS: u64 = 1_000_000_000;
+
+    //Alice offers 1 SOL out of her wallet for EXPECTED_AMOUNT Y, SOL fees go to the fee owner's wallet
+    let instructions = [
+        system_instruction::transfer(&payer, &alice.pubkey(), 2 * LAMPORTS),
+        system_instruction::transfer(&payer, &trade.fee_owner, LAMPORTS),
+        system_instruction::create_account(
+            &payer,
+            &escrow.pubkey(),
+            rent.minimum_balance(Escrow::LEN),
+            Escrow::LEN as u64,
+            &trade.program_id,
+        ),
+        instruction::init_escrow(
+            &trade.program_id,
+            &system_program::id(),
+            &alice.pubkey(),
+            &vault,
+            &trade.alice_y,
+            &escrow.pubkey(),
+            &system_program::id(),
+            &trade.mint_y,
+            EXPECTED_AMOUNT,
+            0,
+            u64::MAX,
+            InitEscrowOptions {
+                deposit: Some((LAMPORTS, alice.pubkey())),
+                ..InitEscrowOptions::default()
+            },
+        ),
+    ];
+    trade.send(&instructions, &[&alice, &escrow]).await.unwrap();
+
+    //the lamports sit in a vault of the program on top of its rent exemption
+    let vault_account = trade.account(&vault).await.unwrap();
+    assert_eq!(vault_account.owner, trade.program_id);
+    assert_eq!(vault_account.lamports, rent.minimum_balance(0) + LAMPORTS);
+    let escrow_info = trade.escrow_info().await;
+    assert_eq!(escrow_info.offered_mint, system_program::id());
+    assert_eq!(escrow_info.offered_decimals, 9);
+
+    let exchange_ix = |offered_fee_account: &Pubkey| {
+        instruction::exchange(
+            &trade.program_id,
+            &system_program::id(),
+            &trade.token_program_y,
+            &bob.pubkey(),
+            &trade.bob_y,
+            &bob.pubkey(),
+            &vault,
+            &alice.pubkey(),
+            &trade.alice_y,
+            &escrow.pubkey(),
+            &system_program::id(),
+            &trade.mint_y,
+            offered_fee_account,
+            &trade.fee_y,
+            LAMPORTS / 2,
+            ExchangeOptions::default(),
+        )
+    };
+    let wrong_fee_account_ix = exchange_ix(&trade.fee_x);
+    let exchange_ix = exchange_ix(&trade.fee_owner);
+    assert_eq!(
+        trade
+            .send(&[wrong_fee_account_ix], &[&bob])
+            .await
+            .unwrap_err(),
+        escrow_error(0, EscrowError::InvalidFeeAccount)
+    );
+
+    //Bob takes half of the SOL straight into his wallet, then the rest
+    let offered_fee = TEST_FEE.offered_fee(LAMPORTS / 2).unwrap();
+    trade
+        .send(std::slice::from_ref(&exchange_ix), &[&bob])
+        .await
+        .unwrap();
+    let bob_lamports = trade.account(&bob.pubkey()).await.unwrap().lamports;
+    assert_eq!(bob_lamports, LAMPORTS / 2 - offered_fee);
+    assert_eq!(trade.balance(trade.alice_y).await, EXPECTED_AMOUNT / 2);
+    trade.send(&[exchange_ix], &[&bob]).await.unwrap();
+    let bob_lamports = trade.account(&bob.pubkey()).await.unwrap().lamports;
+    assert_eq!(bob_lamports, LAMPORTS - 2 * offered_fee);
+    let fee_owner = trade.fee_owner;
+    let fee_owner_lamports = trade.account(&fee_owner).await.unwrap().lamports;
+    assert_eq!(fee_owner_lamports, LAMPORTS + 2 * offered_fee);
+
+    //the vault and escrow are closed, Alice got both rents back
+    assert!(trade.account(&vault).await.is_none());
+    assert!(trade.account(&escrow.pubkey()).await.is_none());
+    let alice_lamports = trade.account(&alice.pubkey()).await.unwrap().lamports;
+    assert_eq!(alice_lamports, LAMPORTS + rent.minimum_balance(Escrow::LEN));
+}
+
+#[tokio::test]
+async fn test_request_native_sol() {
+    let mut trade = Trade::new().await;
+    let alice = trade.alice.pubkey();
+    let bob = trade.bob.pubkey();
+    let payer = trade.context.payer.pubkey();
+    const LAMPORTS: u64 = 1_000_000_000;
+    let instructions = [
+        system_instruction::transfer(&payer, &alice, LAMPORTS),
+        system_instruction::transfer(&payer, &bob, LAMPORTS),
+        system_instruction::transfer(&payer, &trade.fee_owner, LAMPORTS),
+    ];
+    trade.send(&instructions, &[]).await.unwrap();
+
+    //Alice wants EXPECTED_AMOUNT lamports for her X tokens, paid into her wallet and out of Bob's
+    trade.mint_y = system_program::id();
+    trade.token_program_y = system_program::id();
+    trade.alice_y = alice;
+    trade.bob_y = bob;
+    trade.fee_y = trade.fee_owner;
+    trade.init_escrow().await;
+    assert_eq!(
+        trade.escrow_info().await.requested_mint,
+        system_program::id()
+    );
+
+    trade.exchange(OFFERED_AMOUNT).await.unwrap();
+    let offered_fee = TEST_FEE.offered_fee(OFFERED_AMOUNT).unwrap();
+    let requested_fee = TEST_FEE.requested_fee(EXPECTED_AMOUNT).unwrap();
+    assert_eq!(
+        trade.balance(trade.bob_x).await,
+        OFFERED_AMOUNT - offered_fee
+    );
+    let bob_lamports = trade.account(&bob).await.unwrap().lamports;
+    assert_eq!(bob_lamports, LAMPORTS - EXPECTED_AMOUNT);
+
+    //Alice also gets the rent of the closed temp and escrow accounts
+    let rent = trade.context.banks_client.get_rent().await.unwrap();
+    let temp_len = token_account_len(&mut trade.context, &trade.mint_x).await;
+    let alice_lamports = trade.account(&alice).await.unwrap().lamports;
+    assert_eq!(
+        alice_lamports,
+        LAMPORTS + EXPECTED_AMOUNT - requested_fee
+            + rent.minimum_balance(temp_len)
+            + rent.minimum_balance(Escrow::LEN)
+    );
+    let fee_owner = trade.fee_owner;
+    let fee_owner_lamports = trade.account(&fee_owner).await.unwrap().lamports;
+    assert_eq!(fee_owner_lamports, LAMPORTS + requested_fee);
+}
+
+#[tokio::test]
+async fn test_exchange_partial_fill() {
+    let mut trade = Trade::new().await;
+    trade.init_escrow().await;
+
+    //Bob takes 30% and pays 30% of the price
+    trade.exchange(300).await.unwrap();
+    assert_eq!(trade.balance(trade.bob_y).await, EXPECTED_AMOUNT - 150);
+    assert_eq!(trade.balance(trade.temp.pubkey()).await, 700);
+    assert_eq!(trade.escrow_info().await.expected_amount, 350);
+
+    //an odd amount rounds in Alice's favour: 1/700 of 350 -> 1
+    trade.exchange(1).await.unwrap();
+    assert_eq!(trade.balance(trade.bob_y).await, EXPECTED_AMOUNT - 151);
+    assert_eq!(trade.escrow_info().await.expected_amount, 349);
+
+    //the rest closes the escrow
+    trade.exchange(699).await.unwrap();
+    assert_eq!(trade.balance(trade.bob_y).await, 0);
+
+    //each fill pays the protocol fee on its own amounts
+    let requested_fee = [150, 1, 349]
+        .iter()
+        .map(|amount| TEST_FEE.requested_fee(*amount).unwrap())
+        .sum::<u64>();
+    let offered_fee = [300, 1, 699]
+        .iter()
+        .map(|amount| TEST_FEE.offered_fee(*amount).unwrap())
+        .sum::<u64>();
+    assert_eq!(
+        trade.balance(trade.alice_y).await,
+        EXPECTED_AMOUNT - requested_fee
+    );
+    assert_eq!(trade.balance(trade.fee_y).await, requested_fee);
+    assert_eq!(
+        trade.balance(trade.bob_x).await,
+        OFFERED_AMOUNT - offered_fee
+    );
+    assert_eq!(trade.balance(trade.fee_x).await, offered_fee);
+    let escrow = trade.escrow.pubkey();
+    assert!(trade.account(&escrow).await.is_none());
+}
+
+#[tokio::test]
+async fn test_exchange_invalid_fill_amount() {
+    let mut trade = Trade::new().await;
+    trade.init_escrow().await;
+
+    assert_eq!(
+        trade.exchange(0).await.unwrap_err(),
+        escrow_error(0, EscrowError::InvalidFillAmount)
+    );
+    assert_eq!(
+        trade.exchange(OFFERED_AMOUNT + 1).await.unwrap_err(),
+        escrow_error(0, EscrowError::ExpectedAmountMismatch)
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_missing_signer() {
+    let mut trade = Trade::new().await;
+    trade.init_escrow().await;
+
+    let mut ix = trade.exchange_instruction(OFFERED_AMOUNT);
+    ix.accounts[0].is_signer = false;
+    assert_eq!(
+        trade.send(&[ix], &[]).await.unwrap_err(),
+        program_error(0, InstructionError::MissingRequiredSignature)
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_wrong_accounts() {
+    let mut trade = Trade::new().await;
+    trade.init_escrow().await;
+    let bob = trade.bob.insecure_clone();
+
+    //a temp token account that isn't the one stored in the escrow
+    let other_temp = create_token_account(&mut trade.context, &trade.mint_x, &bob.pubkey()).await;
+    mint_to(
+        &mut trade.context,
+        &trade.mint_x,
+        &other_temp,
+        OFFERED_AMOUNT,
+    )
+    .await;
+    let mut ix = trade.exchange_instruction(OFFERED_AMOUNT);
+    ix.accounts[3].pubkey = other_temp;
+    assert_eq!(
+        trade.send(&[ix], &[&bob]).await.unwrap_err(),
+        program_error(0, InstructionError::InvalidAccountData)
+    );
+
+    //somebody else posing as the initializer
+    let mut ix = trade.exchange_instruction(OFFERED_AMOUNT);
+    ix.accounts[4].pubkey = bob.pubkey();
+    assert_eq!(
+        trade.send(&[ix], &[&bob]).await.unwrap_err(),
+        program_error(0, InstructionError::InvalidAccountData)
+    );
+
+    //Alice's payment redirected to another account
+    let mut ix = trade.exchange_instruction(OFFERED_AMOUNT);
+    ix.accounts[5].pubkey = trade.bob_y;
+    assert_eq!(
+        trade.send(&[ix], &[&bob]).await.unwrap_err(),
+        program_error(0, InstructionError::InvalidAccountData)
+    );
+
+    //Bob paying with the wrong token
+    let mut ix = trade.exchange_instruction(OFFERED_AMOUNT);
+    ix.accounts[1].pubkey = trade.bob_x;
+    assert_eq!(
+        trade.send(&[ix], &[&bob]).await.unwrap_err(),
+        escrow_error(0, EscrowError::RequestedMintMismatch)
+    );
+
+    //Bob receiving into an account of the wrong token
+    let mut ix = trade.exchange_instruction(OFFERED_AMOUNT);
+    ix.accounts[2].pubkey = trade.bob_y;
+    assert_eq!(
+        trade.send(&[ix], &[&bob]).await.unwrap_err(),
+        escrow_error(0, EscrowError::OfferedMintMismatch)
+    );
+
+    //a PDA that isn't this escrow's
+    let mut ix = trade.exchange_instruction(OFFERED_AMOUNT);
+    ix.accounts[8].pubkey = Escrow::find_authority_address(&other_temp, &trade.program_id).0;
+    assert_eq!(
+        trade.send(&[ix], &[&bob]).await.unwrap_err(),
+        program_error(0, InstructionError::InvalidSeeds)
+    );
+
+    //fee accounts that don't belong to the protocol
+    let mut ix = trade.exchange_instruction(OFFERED_AMOUNT);
+    ix.accounts[10].pubkey = trade.alice_y;
+    assert_eq!(
+        trade.send(&[ix], &[&bob]).await.unwrap_err(),
+        escrow_error(0, EscrowError::InvalidFeeAccount)
+    );
+    let mut ix = trade.exchange_instruction(OFFERED_AMOUNT);
+    ix.accounts[9].pubkey = trade.fee_y;
+    assert_eq!(
+        trade.send(&[ix], &[&bob]).await.unwrap_err(),
+        escrow_error(0, EscrowError::InvalidFeeAccount)
+    );
+
+    //mints and token programs that don't belong to the trade
+    let mut ix = trade.exchange_instruction(OFFERED_AMOUNT);
+    ix.accounts[13].pubkey = trade.mint_y;
+    assert_eq!(
+        trade.send(&[ix], &[&bob]).await.unwrap_err(),
+        escrow_error(0, EscrowError::OfferedMintMismatch)
+    );
+    let mut ix = trade.exchange_instruction(OFFERED_AMOUNT);
+    ix.accounts[14].pubkey = trade.mint_x;
+    assert_eq!(
+        trade.send(&[ix], &[&bob]).await.unwrap_err(),
+        escrow_error(0, EscrowError::RequestedMintMismatch)
+    );
+    let mut ix = trade.exchange_instruction(OFFERED_AMOUNT);
+    ix.accounts[12].pubkey = spl_token_2022::id();
+    assert_eq!(
+        trade.send(&[ix], &[&bob]).await.unwrap_err(),
+        program_error(0, InstructionError::IncorrectProgramId)
+    );
+
+    //a config that isn't the program's config PDA
+    let mut ix = trade.exchange_instruction(OFFERED_AMOUNT);
+    ix.accounts[11].pubkey = trade.escrow.pubkey();
+    assert_eq!(
+        trade.send(&[ix], &[&bob]).await.unwrap_err(),
+        program_error(0, InstructionError::InvalidAccountData)
+    );
+
+    //nothing moved
+    assert_eq!(trade.balance(trade.temp.pubkey()).await, OFFERED_AMOUNT);
+    assert_eq!(trade.balance(trade.bob_y).await, EXPECTED_AMOUNT);
+}
+
+#[tokio::test]
+async fn test_exchange_private() {
+    let mut trade = Trade::new().await;
+    trade.taker = Some(trade.bob.pubkey());
+    trade.init_escrow().await;
+    assert_eq!(trade.escrow_info().await.taker, Some(trade.bob.pubkey()));
+
+    //Carol has the tokens Alice wants, but the trade was negotiated with Bob
+    let carol = Keypair::new();
+    let carol_x = create_token_account(&mut trade.context, &trade.mint_x, &carol.pubkey()).await;
+    let carol_y = create_token_account(&mut trade.context, &trade.mint_y, &carol.pubkey()).await;
+    mint_to(&mut trade.context, &trade.mint_y, &carol_y, EXPECTED_AMOUNT).await;
+    let mut ix = trade.exchange_instruction(OFFERED_AMOUNT);
+    ix.accounts[0].pubkey = carol.pubkey();
+    ix.accounts[1].pubkey = carol_y;
+    ix.accounts[2].pubkey = carol_x;
+    assert_eq!(
+        trade.send(&[ix], &[&carol]).await.unwrap_err(),
+        escrow_error(0, EscrowError::TakerMismatch)
+    );
+    assert_eq!(trade.balance(carol_y).await, EXPECTED_AMOUNT);
+
+    trade.exchange(OFFERED_AMOUNT).await.unwrap();
+    assert_eq!(trade.balance(trade.bob_y).await, 0);
+}
+
+#[test]
+fn test_dutch_auction_price() {
+    let mut auction = DutchAuction {
+        start_amount: 1_000,
+        start_time: 100,
+        end_time: 200,
+        step: 0,
+    };
+    assert_eq!(auction.current_amount(500, 0).unwrap(), 1_000);
+    assert_eq!(auction.current_amount(500, 100).unwrap(), 1_000);
+    //the discount rounds down: 500 * 33 / 100 = 165.x
+    assert_eq!(auction.current_amount(500, 133).unwrap(), 835);
+    assert_eq!(auction.current_amount(500, 200).unwrap(), 500);
+    assert_eq!(auction.current_amount(500, u64::MAX).unwrap(), 500);
+
+    //stepwise the price only drops every 25 seconds
+    auction.step = 25;
+    assert_eq!(auction.current_amount(500, 124).unwrap(), 1_000);
+    assert_eq!(auction.current_amount(500, 133).unwrap(), 875);
+    assert_eq!(auction.current_amount(500, 199).unwrap(), 625);
+    assert_eq!(auction.current_amount(500, 200).unwrap(), 500);
+
+    //the price can't rise and the auction needs some time to fall in
+    assert!(auction.current_amount(1_001, 150).is_err());
+    auction.end_time = auction.start_time;
+    assert!(!auction.is_valid(500));
+}
+
+#[tokio::test]
+async fn test_exchange_dutch_auction() {
+    let mut trade = Trade::new().await;
+    trade.auction = Some(Auction::Dutch(DutchAuction {
+        start_amount: 2 * EXPECTED_AMOUNT,
+        start_time: 1_000,
+        end_time: 2_000,
+        step: 0,
+    }));
+    let bob_y = trade.bob_y;
+    mint_to(&mut trade.context, &trade.mint_y, &bob_y, EXPECTED_AMOUNT).await;
+    trade.init_escrow().await;
+
+    //halfway through the price of everything is 750, Bob takes 40% of it for 300
+    trade.set_unix_timestamp(1_500).await;
+    trade.exchange(400).await.unwrap();
+    assert_eq!(trade.balance(bob_y).await, 2 * EXPECTED_AMOUNT - 300);
+
+    //the remaining 60% keep falling from 600 to 300
+    let escrow_info = trade.escrow_info().await;
+    assert_eq!(escrow_info.expected_amount, 300);
+    assert_eq!(escrow_info.dutch_auction.unwrap().start_amount, 600);
+
+    //after the auction ended the rest sells at the floor
+    trade.set_unix_timestamp(2_500).await;
+    trade.exchange(600).await.unwrap();
+    assert_eq!(trade.balance(bob_y).await, 2 * EXPECTED_AMOUNT - 600);
+    let requested_fee = TEST_FEE.requested_fee(300).unwrap() * 2;
+    assert_eq!(trade.balance(trade.alice_y).await, 600 - requested_fee);
+}
+
+#[tokio::test]
+async fn test_init_escrow_invalid_dutch_auction() {
+    let mut trade = Trade::new().await;
+    //the start price is below the floor
+    trade.auction = Some(Auction::Dutch(DutchAuction {
+        start_amount: EXPECTED_AMOUNT - 1,
+        start_time: 1_000,
+        end_time: 2_000,
+        step: 0,
+    }));
+    let instructions = trade.init_escrow_instructions(0, u64::MAX).await;
+    let alice = trade.alice.insecure_clone();
+    let temp = trade.temp.insecure_clone();
+    let escrow = trade.escrow.insecure_clone();
+    assert_eq!(
+        trade
+            .send(&instructions, &[&alice, &temp, &escrow])
+            .await
+            .unwrap_err(),
+        escrow_error(4, EscrowError::InvalidInstruction)
+    );
+}
+
+#[tokio::test]
+async fn test_english_auction() {
+    let mut trade = Trade::new().await;
+    trade.init_english_auction().await;
+    let bid_token_account = trade.bid_token_account.unwrap();
+    let bob = trade.bob.insecure_clone();
+    let carol = Keypair::new();
+    let carol_x = create_token_account(&mut trade.context, &trade.mint_x, &carol.pubkey()).await;
+    let carol_y = create_token_account(&mut trade.context, &trade.mint_y, &carol.pubkey()).await;
+    mint_to(
+        &mut trade.context,
+        &trade.mint_y,
+        &carol_y,
+        2 * EXPECTED_AMOUNT,
+    )
+    .await;
+
+    //bids start at the reserve price
+    let ix = trade.place_bid_instruction(&bob.pubkey(), &trade.bob_y, &trade.bob_y, 499);
+    assert_eq!(
+        trade.send(&[ix], &[&bob]).await.unwrap_err(),
+        escrow_error(0, EscrowError::BidTooLow)
+    );
+    let ix = trade.place_bid_instruction(&bob.pubkey(), &trade.bob_y, &trade.bob_y, 500);
+    trade.send(&[ix], &[&bob]).await.unwrap();
+    assert_eq!(trade.balance(bid_token_account).await, 500);
+
+    //Carol has to beat Bob by the min increment, Bob gets his bid back
+    let ix = trade.place_bid_instruction(&carol.pubkey(), &carol_y, &trade.bob_y, 549);
+    assert_eq!(
+        trade.send(&[ix], &[&carol]).await.unwrap_err(),
+        escrow_error(0, EscrowError::BidTooLow)
+    );
+    let ix = trade.place_bid_instruction(&carol.pubkey(), &carol_y, &carol_y, 550);
+    assert_eq!(
+        trade.send(&[ix], &[&carol]).await.unwrap_err(),
+        program_error(0, InstructionError::InvalidAccountData)
+    );
+    let ix = trade.place_bid_instruction(&carol.pubkey(), &carol_y, &trade.bob_y, 550);
+    trade.send(&[ix], &[&carol]).await.unwrap();
+    assert_eq!(trade.balance(trade.bob_y).await, EXPECTED_AMOUNT);
+    assert_eq!(trade.balance(bid_token_account).await, 550);
+    assert_eq!(
+        trade.escrow_info().await.highest_bid.unwrap().bidder,
+        carol.pubkey()
+    );
+
+    //the auction can't be taken or cancelled, and only settled once it's over
+    assert_eq!(
+        trade.exchange(OFFERED_AMOUNT).await.unwrap_err(),
+        escrow_error(0, EscrowError::InvalidEscrowMode)
+    );
+    let alice = trade.alice.insecure_clone();
+    let ix = trade.cancel_instruction();
+    assert_eq!(
+        trade.send(&[ix], &[&alice]).await.unwrap_err(),
+        escrow_error(0, EscrowError::InvalidEscrowMode)
+    );
+    let ix = trade.settle_auction_instruction(&carol_x);
+    assert_eq!(
+        trade.send(&[ix], &[]).await.unwrap_err(),
+        escrow_error(0, EscrowError::EscrowNotExpired)
+    );
+
+    trade.set_unix_timestamp(2_001).await;
+    let ix = trade.place_bid_instruction(&bob.pubkey(), &trade.bob_y, &carol_y, 600);
+    assert_eq!(
+        trade.send(&[ix], &[&bob]).await.unwrap_err(),
+        escrow_error(0, EscrowError::EscrowTimeOverflow)
+    );
+
+    //the offered tokens may only go to the winner
+    let ix = trade.settle_auction_instruction(&trade.bob_x);
+    assert_eq!(
+        trade.send(&[ix], &[]).await.unwrap_err(),
+        program_error(0, InstructionError::InvalidAccountData)
+    );
+    let ix = trade.settle_auction_instruction(&carol_x);
+    trade.send(&[ix], &[]).await.unwrap();
+
+    let offered_fee = TEST_FEE.offered_fee(OFFERED_AMOUNT).unwrap();
+    let requested_fee = TEST_FEE.requested_fee(550).unwrap();
+    assert_eq!(trade.balance(carol_x).await, OFFERED_AMOUNT - offered_fee);
+    assert_eq!(trade.balance(trade.fee_x).await, offered_fee);
+    assert_eq!(trade.balance(trade.alice_y).await, 550 - requested_fee);
+    assert_eq!(trade.balance(trade.fee_y).await, requested_fee);
+    for account in [
+        trade.temp.pubkey(),
+        bid_token_account,
+        trade.escrow.pubkey(),
+    ] {
+        assert!(trade.account(&account).await.is_none());
+    }
+}
+
+#[tokio::test]
+async fn test_english_auction_rejects_closable_accounts() {
+    let mut trade = Trade::new().await;
+    let alice = trade.alice.insecure_clone();
+    let temp = trade.temp.insecure_clone();
+    let escrow = trade.escrow.insecure_clone();
+    let bid_token_account =
+        create_token_account(&mut trade.context, &trade.mint_y, &alice.pubkey()).await;
+    trade.bid_token_account = Some(bid_token_account);
+    trade.auction = Some(Auction::English(EnglishAuction { min_increment: 50 }));
+    let instructions = trade.init_escrow_instructions(0, 2_000).await;
+
+    //a close authority Alice kept would stop the PDA from closing the account, locking up the bids for good
+    let set_close_authority = |token_program: &Pubkey, account: &Pubkey| {
+        spl_token_2022::instruction::set_authority(
+            token_program,
+            account,
+            Some(&alice.pubkey()),
+            spl_token_2022::instruction::AuthorityType::CloseAccount,
+            &alice.pubkey(),
+            &[],
+        )
+        .unwrap()
+    };
+    let mut closable_bids = instructions.clone();
+    closable_bids.insert(
+        0,
+        set_close_authority(&trade.token_program_y, &bid_token_account),
+    );
+    let mut closable_temp = instructions.clone();
+    closable_temp.insert(
+        2,
+        set_close_authority(&trade.token_program_x, &temp.pubkey()),
+    );
+    for instructions in [closable_bids, closable_temp] {
+        assert_eq!(
+            trade
+                .send(&instructions, &[&alice, &temp, &escrow])
+                .await
+                .unwrap_err(),
+            program_error(5, InstructionError::InvalidAccountData)
+        );
+    }
+
+    //the bid token account has to start out empty
+    mint_to(&mut trade.context, &trade.mint_y, &bid_token_account, 1).await;
+    assert_eq!(
+        trade
+            .send(&instructions, &[&alice, &temp, &escrow])
+            .await
+            .unwrap_err(),
+        program_error(4, InstructionError::InvalidAccountData)
+    );
+}
+
+#[tokio::test]
+async fn test_english_auction_without_bids() {
+    let mut trade = Trade::new().await;
+    trade.init_english_auction().await;
+
+    //nobody bid, so Alice gets her tokens back
+    trade.set_unix_timestamp(2_001).await;
+    let ix = trade.settle_auction_instruction(&trade.alice_x);
+    trade.send(&[ix], &[]).await.unwrap();
+    assert_eq!(trade.balance(trade.alice_x).await, OFFERED_AMOUNT);
+    assert_eq!(trade.balance(trade.fee_x).await, 0);
+    let escrow = trade.escrow.pubkey();
+    assert!(trade.account(&escrow).await.is_none());
+}
+
+#[tokio::test]
+async fn test_exchange_token_2022() {
+    //Token-2022 X with a 1% transfer fee traded for original token program Y
+    let mut trade = Trade::with_mints(
+        TestMint::Token2022 {
+            transfer_fee_bps: 100,
+        },
+        TestMint::Token,
+    )
+    .await;
+    trade.init_escrow().await;
+
+    //the mint withheld 10 of the 1000 on the way into the escrow
+    let temp = trade.temp.pubkey();
+    assert_eq!(trade.balance(temp).await, 990);
+
+    trade.exchange(990).await.unwrap();
+    let offered_fee = TEST_FEE.offered_fee(990).unwrap();
+    let requested_fee = TEST_FEE.requested_fee(EXPECTED_AMOUNT).unwrap();
+    assert_eq!(trade.balance(trade.bob_x).await, 990 - offered_fee - 10);
+    assert_eq!(
+        trade.balance(trade.alice_y).await,
+        EXPECTED_AMOUNT - requested_fee
+    );
+    assert_eq!(trade.balance(trade.fee_y).await, requested_fee);
+
+    //the temp token account still held withheld fees, they were harvested so it could be closed
+    assert!(trade.account(&temp).await.is_none());
+    let escrow = trade.escrow.pubkey();
+    assert!(trade.account(&escrow).await.is_none());
+}
+
+#[tokio::test]
+async fn test_exchange_token_2022_requested_transfer_fee() {
+    //the requested Y charges a 1% transfer fee, Bob pays it on top so Alice and the protocol get their full share
+    let mut trade = Trade::with_mints(
+        TestMint::Token,
+        TestMint::Token2022 {
+            transfer_fee_bps: 100,
+        },
+    )
+    .await;
+    mint_to(&mut trade.context, &trade.mint_y, &trade.bob_y, 100).await;
+    trade.init_escrow().await;
+
+    trade.exchange(OFFERED_AMOUNT).await.unwrap();
+    let requested_fee = TEST_FEE.requested_fee(EXPECTED_AMOUNT).unwrap();
+    assert_eq!(
+        trade.balance(trade.alice_y).await,
+        EXPECTED_AMOUNT - requested_fee
+    );
+    assert_eq!(trade.balance(trade.fee_y).await, requested_fee);
+    //499 arrive after sending 505 and 1 after sending 2
+    assert_eq!(
+        trade.balance(trade.bob_y).await,
+        EXPECTED_AMOUNT + 100 - 505 - 2
+    );
+}
+
+#[tokio::test]
+async fn test_cancel_token_2022() {
+    let mut trade = Trade::with_mints(
+        TestMint::Token2022 {
+            transfer_fee_bps: 100,
+        },
+        TestMint::Token,
+    )
+    .await;
+    trade.init_escrow().await;
+    let alice = trade.alice.insecure_clone();
+
+    let ix = trade.cancel_instruction();
+    trade.send(&[ix], &[&alice]).await.unwrap();
+
+    //the fee is withheld on the way in and again on the way back
+    assert_eq!(trade.balance(trade.alice_x).await, 990 - 10);
+    let temp = trade.temp.pubkey();
+    assert!(trade.account(&temp).await.is_none());
+}
+
+#[tokio::test]
+async fn test_exchange_decimals_changed() {
+    let mut trade = Trade::new().await;
+    trade.init_escrow().await;
+
+    //the requested mint now has 6 decimals instead of the 0 Alice priced the trade in
+    //(possible with Token-2022, whose mints can be closed and created again at the same address)
+    let mint_y = trade.mint_y;
+    let mut mint_account = trade.account(&mint_y).await.unwrap();
+    let mut mint_info = Mint::unpack(&mint_account.data).unwrap();
+    mint_info.decimals = 6;
+    Mint::pack(mint_info, &mut mint_account.data).unwrap();
+    trade.context.set_account(&mint_y, &mint_account.into());
+
+    assert_eq!(
+        trade.exchange(OFFERED_AMOUNT).await.unwrap_err(),
+        program_error(
+            0,
+            InstructionError::Custom(spl_token::error::TokenError::MintDecimalsMismatch as u32)
+        )
+    );
+    assert_eq!(trade.balance(trade.bob_y).await, EXPECTED_AMOUNT);
+}
+
+#[tokio::test]
+async fn test_exchange_time_window() {
+    let mut trade = Trade::new().await;
+    let instructions = trade.init_escrow_instructions(1_000, 2_000).await;
+    let alice = trade.alice.insecure_clone();
+    let temp = trade.temp.insecure_clone();
+    let escrow = trade.escrow.insecure_clone();
+    trade
+        .send(&instructions, &[&alice, &temp, &escrow])
+        .await
+        .unwrap();
+
+    trade.set_unix_timestamp(999).await;
+    assert_eq!(
+        trade.exchange(OFFERED_AMOUNT).await.unwrap_err(),
+        escrow_error(0, EscrowError::EscrowTimeUnlock)
+    );
+
+    trade.set_unix_timestamp(2_001).await;
+    assert_eq!(
+        trade.exchange(OFFERED_AMOUNT).await.unwrap_err(),
+        escrow_error(0, EscrowError::EscrowTimeOverflow)
+    );
+
+    trade.set_unix_timestamp(1_500).await;
+    trade.exchange(OFFERED_AMOUNT).await.unwrap();
+    assert_eq!(trade.balance(trade.bob_y).await, 0);
+}
+
+#[tokio::test]
+async fn test_update_expected_amount() {
+    let mut trade = Trade::new().await;
+    trade.init_escrow().await;
+    let alice = trade.alice.insecure_clone();
+    let bob = trade.bob.insecure_clone();
+    let escrow = trade.escrow.pubkey();
+    let program_id = trade.program_id;
+    let update_ix = |amount, deadlines| {
+        instruction::update_expected_amount(
+            &program_id,
+            &alice.pubkey(),
+            &escrow,
+            amount,
+            deadlines,
+        )
+    };
+
+    //Alice lowers the price and moves the time window
+    let ix = update_ix(400, Some((1_000, 2_000)));
+    trade.send(&[ix], &[&alice]).await.unwrap();
+    let escrow_info = trade.escrow_info().await;
+    assert_eq!(escrow_info.expected_amount, 400);
+    assert_eq!(escrow_info.unlock_time, 1_000);
+    assert_eq!(escrow_info.time_out, 2_000);
+
+    //leaving out the deadlines keeps them
+    let ix = update_ix(450, None);
+    trade.send(&[ix], &[&alice]).await.unwrap();
+    let escrow_info = trade.escrow_info().await;
+    assert_eq!(escrow_info.expected_amount, 450);
+    assert_eq!(escrow_info.time_out, 2_000);
+
+    for (amount, deadlines) in [(0, None), (400, Some((2_000, 2_000)))] {
+        let ix = update_ix(amount, deadlines);
+        assert_eq!(
+            trade.send(&[ix], &[&alice]).await.unwrap_err(),
+            escrow_error(0, EscrowError::InvalidInstruction)
+        );
+    }
+
+    //only Alice may reprice
+    let mut ix = update_ix(1, None);
+    ix.accounts[0].pubkey = bob.pubkey();
+    assert_eq!(
+        trade.send(&[ix], &[&bob]).await.unwrap_err(),
+        program_error(0, InstructionError::InvalidAccountData)
+    );
+
+    //Bob only agreed to pay 400, the price went up to 450 before his exchange landed
+    trade.set_unix_timestamp(1_500).await;
+    let mut ix = trade.exchange_instruction(OFFERED_AMOUNT);
+    ix.data = EscrowInstruction::Exchange {
+        amount: OFFERED_AMOUNT,
+        max_requested_amount: Some(400),
+        create_receive_account: false,
+    }
+    .pack();
+    assert_eq!(
+        trade.send(&[ix], &[&bob]).await.unwrap_err(),
+        escrow_error(0, EscrowError::PriceLimitExceeded)
+    );
+
+    let mut ix = trade.exchange_instruction(OFFERED_AMOUNT);
+    ix.data = EscrowInstruction::Exchange {
+        amount: OFFERED_AMOUNT,
+        max_requested_amount: Some(450),
+        create_receive_account: false,
+    }
+    .pack();
+    trade.send(&[ix], &[&bob]).await.unwrap();
+    assert_eq!(trade.balance(trade.bob_y).await, EXPECTED_AMOUNT - 450);
+}
+
+#[tokio::test]
+async fn test_cancel() {
+    let mut trade = Trade::new().await;
+    trade.init_escrow().await;
+    let alice = trade.alice.insecure_clone();
+    let temp = trade.temp.pubkey();
+    let escrow = trade.escrow.pubkey();
+    let escrow_lamports = trade.account(&escrow).await.unwrap().lamports;
+    let temp_lamports = trade.account(&temp).await.unwrap().lamports;
+
+    let ix = trade.cancel_instruction();
+    trade.send(&[ix], &[&alice]).await.unwrap();
+
+    assert_eq!(trade.balance(trade.alice_x).await, OFFERED_AMOUNT);
+    assert!(trade.account(&temp).await.is_none());
+    assert!(trade.account(&escrow).await.is_none());
+    assert_eq!(
+        trade.account(&alice.pubkey()).await.unwrap().lamports,
+        escrow_lamports + temp_lamports
+    );
+}
+
+#[tokio::test]
+async fn test_cancel_not_initializer() {
+    let mut trade = Trade::new().await;
+    trade.init_escrow().await;
+    let bob = trade.bob.insecure_clone();
+
+    let mut ix = trade.cancel_instruction();
+    ix.accounts[0].is_signer = false;
+    assert_eq!(
+        trade.send(&[ix], &[]).await.unwrap_err(),
+        program_error(0, InstructionError::MissingRequiredSignature)
+    );
+
+    let mut ix = trade.cancel_instruction();
+    ix.accounts[0].pubkey = bob.pubkey();
+    ix.accounts[2].pubkey = trade.bob_x;
+    assert_eq!(
+        trade.send(&[ix], &[&bob]).await.unwrap_err(),
+        program_error(0, InstructionError::InvalidAccountData)
+    );
+    assert_eq!(trade.balance(trade.temp.pubkey()).await, OFFERED_AMOUNT);
+}
+
+#[tokio::test]
+async fn test_expire() {
+    let mut trade = Trade::new().await;
+    let instructions = trade.init_escrow_instructions(0, 2_000).await;
+    let alice = trade.alice.insecure_clone();
+    let temp = trade.temp.insecure_clone();
+    let escrow = trade.escrow.insecure_clone();
+    trade
+        .send(&instructions, &[&alice, &temp, &escrow])
+        .await
+        .unwrap();
+    let escrow_lamports = trade.account(&escrow.pubkey()).await.unwrap().lamports;
+    let temp_lamports = trade.account(&temp.pubkey()).await.unwrap().lamports;
+
+    //a keeper bot with some lamports to pay for its transactions
+    let keeper = Keypair::new();
+    trade
+        .send(
+            &[system_instruction::transfer(
+                &trade.context.payer.pubkey(),
+                &keeper.pubkey(),
+                1_000_000_000,
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+
+    trade.set_unix_timestamp(2_000).await;
+    let ix = trade.expire_instruction(&keeper.pubkey());
+    assert_eq!(
+        trade.send(&[ix], &[&keeper]).await.unwrap_err(),
+        escrow_error(0, EscrowError::EscrowNotExpired)
+    );
+
+    trade.set_unix_timestamp(2_001).await;
+
+    //the tokens can't be sent anywhere but to Alice
+    let mut ix = trade.expire_instruction(&keeper.pubkey());
+    ix.accounts[2].pubkey = trade.bob_x;
+    assert_eq!(
+        trade.send(&[ix], &[&keeper]).await.unwrap_err(),
+        program_error(0, InstructionError::InvalidAccountData)
+    );
+
+    //the keeper pays the transaction fee with another account, so its balance only changes by the bounty
+    let ix = trade.expire_instruction(&keeper.pubkey());
+    trade.send(&[ix], &[&keeper]).await.unwrap();
+
+    assert_eq!(trade.balance(trade.alice_x).await, OFFERED_AMOUNT);
+    assert!(trade.account(&temp.pubkey()).await.is_none());
+    assert!(trade.account(&escrow.pubkey()).await.is_none());
+    assert_eq!(
+        trade.account(&keeper.pubkey()).await.unwrap().lamports,
+        1_000_000_000 + TEST_EXPIRE_BOUNTY
+    );
+    assert_eq!(
+        trade.account(&alice.pubkey()).await.unwrap().lamports,
+        escrow_lamports + temp_lamports - TEST_EXPIRE_BOUNTY
+    );
+}
+
+#[tokio::test]
+async fn test_migrate_legacy_escrow() {
+    let mut trade = Trade::new().await;
+
+    //what the first version of the program left behind: a temp token account owned by the program-wide PDA
+    //and a 121 byte escrow account without bump, mints or deadlines
+    let (legacy_pda, _bump_seed) =
+        Pubkey::find_program_address(&[Escrow::AUTHORITY_SEED], &trade.program_id);
+    let mut instructions = trade.init_escrow_instructions(0, u64::MAX).await;
+    instructions.truncate(3);
+    instructions.push(
+        spl_token::instruction::set_authority(
+            &spl_token::id(),
+            &trade.temp.pubkey(),
+            Some(&legacy_pda),
+            spl_token::instruction::AuthorityType::AccountOwner,
+            &trade.alice.pubkey(),
+            &[],
+        )
+        .unwrap(),
+    );
+    let alice = trade.alice.insecure_clone();
+    let temp = trade.temp.insecure_clone();
+    trade.send(&instructions, &[&alice, &temp]).await.unwrap();
+
+    let mut legacy_data = vec![1];
+    legacy_data.extend_from_slice(alice.pubkey().as_ref());
+    legacy_data.extend_from_slice(temp.pubkey().as_ref());
+    legacy_data.extend_from_slice(trade.alice_y.as_ref());
+    legacy_data.extend_from_slice(&EXPECTED_AMOUNT.to_le_bytes());
+    legacy_data.extend_from_slice(&[0; 16]);
+    assert_eq!(legacy_data.len(), Escrow::version_len(1).unwrap());
+    let rent = trade.context.banks_client.get_rent().await.unwrap();
+    trade.context.set_account(
+        &trade.escrow.pubkey(),
+        &Account {
+            lamports: rent.minimum_balance(legacy_data.len()),
+            data: legacy_data,
+            owner: trade.program_id,
+            ..Account::default()
+        }
+        .into(),
+    );
+
+    //the current program refuses to trade on the old layout
+    assert_eq!(
+        trade.exchange(OFFERED_AMOUNT).await.unwrap_err(),
+        program_error(0, InstructionError::InvalidAccountData)
+    );
+
+    //anybody can migrate it, Bob pays the extra rent
+    let bob = trade.bob.insecure_clone();
+    trade
+        .send(
+            &[system_instruction::transfer(
+                &trade.context.payer.pubkey(),
+                &bob.pubkey(),
+                1_000_000_000,
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    let ix = instruction::migrate_escrow(
+        &trade.program_id,
+        &bob.pubkey(),
+        &trade.escrow.pubkey(),
+        &temp.pubkey(),
+        &trade.alice_y,
+        &trade.mint_x,
+        &trade.mint_y,
+    );
+    //the legacy PDA only signs for the token program
+    let mut wrong_token_program_ix = ix.clone();
+    wrong_token_program_ix.accounts[4].pubkey = Pubkey::new_unique();
+    assert_eq!(
+        trade
+            .send(&[wrong_token_program_ix], &[&bob])
+            .await
+            .unwrap_err(),
+        program_error(0, InstructionError::IncorrectProgramId)
+    );
+    trade
+        .send(std::slice::from_ref(&ix), &[&bob])
+        .await
+        .unwrap();
+
+    let escrow = trade.escrow.pubkey();
+    let escrow_account = trade.account(&escrow).await.unwrap();
+    assert_eq!(escrow_account.data.len(), Escrow::LEN);
+    assert_eq!(escrow_account.data[0], Escrow::VERSION);
+    assert!(rent.is_exempt(escrow_account.lamports, Escrow::LEN));
+
+    let escrow_info = trade.escrow_info().await;
+    let (pda, bump_seed) = Escrow::find_authority_address(&escrow, &trade.program_id);
+    assert_eq!(escrow_info.initializer_pubkey, alice.pubkey());
+    assert_eq!(escrow_info.expected_amount, EXPECTED_AMOUNT);
+    assert_eq!(escrow_info.unlock_time, 0);
+    assert_eq!(escrow_info.time_out, u64::MAX);
+    assert_eq!(escrow_info.bump_seed, bump_seed);
+    assert_eq!(escrow_info.offered_mint, trade.mint_x);
+    assert_eq!(escrow_info.requested_mint, trade.mint_y);
+    let temp_account = trade.account(&temp.pubkey()).await.unwrap();
+    assert_eq!(unpack_token_account(&temp_account.data).owner, pda);
+
+    //migrating again changes nothing
+    trade.send(&[ix], &[&bob]).await.unwrap();
+    assert_eq!(trade.account(&escrow).await.unwrap(), escrow_account);
+
+    trade.exchange(OFFERED_AMOUNT).await.unwrap();
+    assert_eq!(trade.balance(trade.bob_y).await, 0);
+    let escrow = trade.escrow.pubkey();
+    assert!(trade.account(&escrow).await.is_none());
+}